@@ -0,0 +1,80 @@
+use crate::structs::GourceLogFormat;
+use anyhow::Context;
+use clap::ValueEnum;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// How much time each row of the --activity-csv covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ActivityBucket {
+    Second,
+    Minute,
+    Hour,
+    Day,
+}
+
+impl ActivityBucket {
+    const fn seconds(self) -> i64 {
+        match self {
+            Self::Second => 1,
+            Self::Minute => 60,
+            Self::Hour => 3600,
+            Self::Day => 86_400,
+        }
+    }
+}
+
+pub struct ActivityConfig {
+    pub path: PathBuf,
+    pub bucket: ActivityBucket,
+}
+
+/// Counts the events in each time bucket as the sorted log is written,
+/// a row is written as soon as the log moves past its bucket
+pub struct ActivityWriter {
+    writer: csv::Writer<io::BufWriter<fs::File>>,
+    bucket_size: i64,
+    current: Option<(i64, u64)>,
+}
+
+impl ActivityWriter {
+    pub fn new(config: &ActivityConfig) -> anyhow::Result<Self> {
+        let file = fs::File::create(&config.path).context("Failed to create activity file")?;
+        let mut writer = csv::Writer::from_writer(io::BufWriter::new(file));
+        writer.write_record(["timestamp", "events"])?;
+        Ok(Self {
+            writer,
+            bucket_size: config.bucket.seconds(),
+            current: None,
+        })
+    }
+
+    pub fn record(&mut self, log: &GourceLogFormat) -> anyhow::Result<()> {
+        let bucket = log.timestamp - log.timestamp.rem_euclid(self.bucket_size);
+        match &mut self.current {
+            Some((start, events)) if *start == bucket => *events += 1,
+            _ => {
+                self.write_current()?;
+                self.current = Some((bucket, 1));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.write_current()?;
+        self.writer
+            .flush()
+            .context("Failed to write activity file")?;
+        Ok(())
+    }
+
+    fn write_current(&mut self) -> anyhow::Result<()> {
+        if let Some((start, events)) = self.current.take() {
+            self.writer
+                .write_record([start.to_string(), events.to_string()])?;
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,16 @@
+use crate::activity::ActivityBucket;
+use crate::output::{ColorBy, LogField, OutputCompression, OutputFormat};
+use crate::source::Backend;
+use crate::stats::StatsFormat;
+use crate::structs::{
+    DateSource, DirectoryLevel, GourceActionType, Identity, MergeAttribution, RepositoryOrder,
+    RootCommitMode,
+};
 use clap::Parser;
+use std::num::{NonZeroU64, NonZeroUsize};
 
+#[allow(clippy::struct_excessive_bools)]
+// Reason: These are independent command line flags, not a state machine
 #[derive(Parser)]
 #[command(
     name = "Rource",
@@ -14,8 +25,13 @@ In some cases you may need to use the --use-merge-sort option, please read the d
 "
 )]
 pub struct ClapArguments {
-    #[arg(short, long, help = "The path to the git repository/repositories")]
-    pub path: String,
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["from_json", "job", "finalize"],
+        help = "The path to the git repository/repositories"
+    )]
+    pub path: Option<String>,
 
     #[arg(
         short,
@@ -28,7 +44,10 @@ pub struct ClapArguments {
         requires = "recursive",
         short,
         long,
-        help = "Used with recursive, only process these repositories, cannot be used with --exclude"
+        help = "Used with recursive, only process these repositories, cannot be used with --exclude",
+        long_help = "Used with recursive, only process the repositories whose directory names match, cannot be used with --exclude. \
+        Each one is a name or a glob like 'payments-*', you can specify this option multiple times. \
+        Directories between <PATH> and a repository have to match too"
     )]
     pub include: Vec<String>,
 
@@ -37,11 +56,20 @@ pub struct ClapArguments {
         conflicts_with = "include",
         short,
         long,
-        help = "Used with recursive, exclude these repositories from processing, cannot be used with --include"
+        help = "Used with recursive, exclude these repositories from processing, cannot be used with --include",
+        long_help = "Used with recursive, exclude the repositories and directories whose names match, cannot be used with --include. \
+        Each one is a name or a glob like 'temp-*', you can specify this option multiple times"
     )]
     pub exclude: Vec<String>,
 
-    #[arg(short, long, help = "Output file, defaults to stdout")]
+    #[arg(
+        short,
+        long,
+        help = "Output file, defaults to stdout",
+        long_help = "Output file, defaults to stdout. \
+        The log is written to <OUTPUT>.partial and renamed to <OUTPUT> once it's complete, \
+        so an interrupted run leaves any previous output untouched"
+    )]
     pub output: Option<String>,
 
     #[arg(
@@ -55,6 +83,29 @@ You can specify this option multiple times"
     )]
     pub alias: Vec<String>,
 
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Read aliases from a file, one <USERNAME>::<REPLACEMENT> per line",
+        long_help = "Read aliases from a UTF-8 file, one <USERNAME>::<REPLACEMENT> per line in the same format as --alias, \
+        for projects with too many aliases to give on the command line. \
+        Blank lines and lines starting with '#' are ignored. \
+        Aliases given with --alias are added after the file's, so they win when both alias the same username"
+    )]
+    pub alias_file: Option<String>,
+
+    #[arg(
+        long,
+        help = "Alias every user whose name matches a regular expression",
+        long_help = "Alias every user whose name matches a regular expression, the format is <PATTERN>::<REPLACEMENT> split at the last '::'. \
+        A name the pattern matches anywhere in is replaced as a whole by REPLACEMENT, where $1 or ${name} is what the pattern's \
+        numbered or named group matched (write ${1} when letters follow it), e.g. '^(.+)@corp\\.com$::$1' or '^[Aa]lice\\b::Alice Smith'. \
+        Patterns are only tried for names without an --alias or --alias-file entry, in the order they were given, and the first match wins. \
+        They're checked when rource starts, and ignore case with --case-insensitive-authors. \
+        You can specify this option multiple times"
+    )]
+    pub alias_regex: Vec<String>,
+
     #[arg(
         long,
         short = 'm',
@@ -82,7 +133,8 @@ Please also read the documentation for --sort-chunk-size and --temp-file-locatio
         help = "Location to use for temporary merge-sort files",
         long_help = "Location to store temporary files, by default this will randomly named \
          directory in the current working path, if the program is interrupted you may \
-         need to delete this directory manually",
+         need to delete this directory manually. A temporary log left in this directory by an interrupted run \
+         is removed before starting, so it can't end up in the new log",
         requires = "use_merge_sort"
     )]
     pub temp_file_location: Option<String>,
@@ -95,4 +147,1116 @@ Please also read the documentation for --sort-chunk-size and --temp-file-locatio
         help = "Maximum changeset size per commit, default is unlimited"
     )]
     pub max_changeset_size: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Exclude common generated, vendored and lock files",
+        long_help = "Exclude a built-in list of generated, vendored and lock files from the log, \
+        e.g. node_modules, vendor, target, package-lock.json and Cargo.lock. \
+        See the readme for the full list, use --sensible-defaults-skip to keep some of them"
+    )]
+    pub sensible_defaults: bool,
+
+    #[arg(
+        long,
+        requires = "sensible_defaults",
+        help = "Used with --sensible-defaults, don't apply this built-in pattern, e.g. '**/vendor/**'",
+        long_help = "Used with --sensible-defaults, don't apply this built-in pattern, \
+        the pattern must match one of the built-in patterns exactly, e.g. '**/vendor/**'. \
+        You can specify this option multiple times"
+    )]
+    pub sensible_defaults_skip: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Only show files whose path in the log matches this glob, can be given multiple times",
+        long_help = "Only show the files whose path in the log matches this glob, e.g. '**/*.rs'. \
+        Paths start with the repository's folder or prefix, so 'backend/src/**' picks out the src folder of the backend repository \
+        in a run over several of them. '*' matches across folders too, the same as the --sensible-defaults patterns. \
+        You can specify this option multiple times, a file matching any of them is shown"
+    )]
+    pub path_include: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Leave out files whose path in the log matches this glob, can be given multiple times",
+        long_help = "Leave out the files whose path in the log matches this glob, e.g. '**/generated/**' or 'web/static/**', \
+        matched the same way as --path-include. A file matching both --path-exclude and --path-include is left out. \
+        You can specify this option multiple times"
+    )]
+    pub path_exclude: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Report progress to a Unix domain socket instead of the terminal",
+        long_help = "Connect to a Unix domain socket and report progress as lines of \
+        <PHASE><TAB><PERCENT> instead of drawing progress bars in the terminal, \
+        a percent of '-' means the phase has no known length. \
+        Writes are best-effort, if the reader falls behind, updates are dropped rather than slowing down processing. \
+        If the reader closes the socket early, progress reports stop and the run carries on"
+    )]
+    pub progress_socket: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        conflicts_with = "progress_socket",
+        help = "Print a line of progress to stderr every few seconds instead of drawing progress bars",
+        long_help = "Print a single 'NN% complete' line to stderr every SECONDS seconds instead of drawing progress bars, \
+        for CI logs that can't show animated bars. The percentage is the average of every phase with a known length so far, \
+        so it's only a rough guide, and it never goes down. Still printed with --quiet"
+    )]
+    pub progress_interval: Option<NonZeroU64>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        ignore_case = true,
+        help = "Only emit these action types, e.g. A,D",
+        long_help = "Only emit events of these action types, A (Added), M (Modified) or D (Deleted), \
+        separated by commas, e.g. '--only-types D' for a deletions only visualisation. \
+        This is applied to each file event after it has been classified, \
+        so if one change is represented as a delete and an add, only the matching half is kept"
+    )]
+    pub only_types: Vec<GourceActionType>,
+
+    #[arg(
+        long,
+        requires = "output",
+        help = "Open the log in Gource once it has been written, requires --output",
+        long_help = "Once the log has been written, run 'gource --log-format custom <OUTPUT>', \
+        if gource can't be found on your PATH, the command to run is printed instead. Requires --output"
+    )]
+    pub preview: bool,
+
+    #[arg(
+        long,
+        requires = "preview",
+        allow_hyphen_values = true,
+        help = "Extra arguments to pass to Gource when using --preview, e.g. \"-s 0.5 --hide filenames\"",
+        long_help = "Extra arguments to pass to Gource when using --preview, \
+        the arguments are split on whitespace, so quoting inside the string is not supported, \
+        e.g. --gource-args \"-s 0.5 --hide filenames\""
+    )]
+    pub gource_args: Option<String>,
+
+    #[arg(
+        long,
+        help = "Number of threads to use, defaults to the number of CPUs",
+        long_help = "Number of threads in the shared pool used to process commits, defaults to the number of CPUs. \
+        Unless --repo-concurrency is set, repositories are also processed on this pool"
+    )]
+    pub threads: Option<NonZeroUsize>,
+
+    #[arg(
+        long,
+        help = "Maximum number of repositories to process at the same time",
+        long_help = "Maximum number of repositories to process at the same time, \
+        useful when processing thousands of repositories to reduce the number of open files and memory usage. \
+        The commits within each repository are still processed in parallel using the pool sized by --threads, \
+        so this only limits how many repositories are open at once, not the total number of threads"
+    )]
+    pub repo_concurrency: Option<NonZeroUsize>,
+
+    #[arg(
+        long,
+        help = "Ignore changes that only change a file's mode, e.g. chmod +x",
+        long_help = "Ignore modifications where only the file mode changed (e.g. chmod +x) and the contents are the same, \
+        useful for repositories with a lot of permission churn"
+    )]
+    pub skip_mode_only_changes: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["path", "recursive"],
+        help = "Read newline delimited JSON records instead of git repositories",
+        long_help = "Read newline delimited JSON records (one object per line with timestamp, username, type and file) \
+        from a previous run instead of reading git repositories. \
+        The records are sorted, filtered and aliased exactly like records read from git, \
+        which lets you regenerate or re-alias a log without walking the repositories again"
+    )]
+    pub from_json: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = RootCommitMode::Full,
+        help = "How to handle the root commit, which usually adds the entire initial import",
+        long_help = "How to handle root commits (commits with no parents), which usually add the entire initial import \
+        and create a huge burst of files in the opening frame of the visualisation. \
+        'full' emits every file (the default), \
+        'summarize' emits a single Added event for each top-level file or directory, so the opening frame only shows the outline of the project, \
+        'skip' emits nothing, so the opening frame is empty and files only appear once they are next changed"
+    )]
+    pub root_commit_mode: RootCommitMode,
+
+    #[arg(
+        long,
+        help = "Create a Gource avatar directory and list the images it needs",
+        long_help = "Create a directory for use with Gource's --user-image-dir and write missing-avatars.txt into it, \
+        listing an image filename for each user (after aliases are applied) that doesn't have an avatar yet. \
+        Gource matches images to users by their exact name, so keep the casing as it appears in the list"
+    )]
+    pub avatar_dir: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Backend::Libgit2,
+        help = "How to read the repositories, libgit2 or the system git command",
+        long_help = "How to read the repositories, 'libgit2' (the default) is the fastest, \
+        'git-cli' parses the output of the system 'git log' command instead, \
+        which is slower but useful on platforms where libgit2 is a problem. Both produce the same output"
+    )]
+    pub backend: Backend,
+
+    #[arg(
+        long,
+        help = "Use the domain of each author's email as their name",
+        long_help = "Replace each author's name with the domain of their email address, \
+        so everyone from @acme.com shows up as a single 'acme.com' user. \
+        Authors without an email address keep their name. \
+        Combine with an alias such as 'acme.com::Acme Corp' to give each domain a friendly label"
+    )]
+    pub group_by_email_domain: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "group_by_email_domain",
+        help = "Use each author's email address as their name",
+        long_help = "Replace each author's name with their email address, for organisations whose emails are consistent \
+        but whose display names aren't. Authors without an email address keep their name. \
+        The .mailmap still applies first, so the log shows each author's canonical email, \
+        and aliases match the email, e.g. 'jdoe@acme.com::Jane Doe'. Committers and co-authors are named the same way"
+    )]
+    pub author_email: bool,
+
+    #[arg(
+        long,
+        help = "Use names and emails as they were committed, ignoring .mailmap",
+        long_help = "Use names and emails as they were committed, ignoring .mailmap. \
+        By default each repository's mailmap (its .mailmap file, or the mailmap.file and mailmap.blob settings) \
+        maps authors and committers to their canonical names and emails, the same as 'git log --use-mailmap', \
+        before any --alias is applied"
+    )]
+    pub no_mailmap: bool,
+
+    #[arg(
+        long,
+        help = "Only attribute commits to their author, ignoring Co-authored-by trailers",
+        long_help = "Only attribute commits to their author, ignoring Co-authored-by trailers. \
+        By default everyone named in a commit's 'Co-authored-by: Name <email>' trailers is shown making the same changes \
+        at the same time as the author, so a commit with two co-authors is written three times. \
+        Aliases and --group-by-email-domain apply to co-authors, .mailmap doesn't. \
+        With --identity committer co-authors aren't shown"
+    )]
+    pub no_co_authors: bool,
+
+    #[arg(
+        long,
+        requires = "output",
+        value_name = "N",
+        help = "Flush the output and write a checkpoint file every N records, so --resume can carry on from it",
+        long_help = "Flush the output file and record how many records have been written, the last timestamp and the size of the output \
+        in <OUTPUT>.partial.checkpoint every N records, so a crash during a long write doesn't lose everything already written. \
+        Until the output is complete it is written to <OUTPUT>.partial, which is then renamed to <OUTPUT> and the checkpoint removed. \
+        Run again with the same options and --resume to carry on from the last checkpoint. \
+        This only covers the write phase, reading and sorting the logs is not checkpointed"
+    )]
+    pub checkpoint_every: Option<NonZeroU64>,
+
+    #[arg(
+        long,
+        requires = "checkpoint_every",
+        conflicts_with_all = ["compress", "json_output"],
+        help = "Carry on writing the output from the last --checkpoint-every checkpoint",
+        long_help = "Carry on writing the output from the last checkpoint an interrupted --checkpoint-every run left. \
+        The repositories are read and sorted again, the records the checkpoint covers are skipped \
+        and the rest are added to <OUTPUT>.partial, after dropping anything written after the checkpoint. \
+        The options and repositories must be the same as the interrupted run, the last record skipped has to have the checkpoint's timestamp. \
+        Without a checkpoint the output is written from the start. Compressed output can't be resumed"
+    )]
+    pub resume: bool,
+
+    #[arg(
+        long,
+        help = "Drop files tracked by Git LFS",
+        long_help = "Drop files tracked by Git LFS (those with filter=lfs in the .gitattributes at the root of the repository, as of HEAD), \
+        which are usually large binaries and media that clutter a visualisation of the code. \
+        Patterns in .gitattributes files in subdirectories are not read"
+    )]
+    pub skip_lfs: bool,
+
+    #[arg(
+        long,
+        requires = "output",
+        help = "Read the output back and check it's sorted correctly",
+        long_help = "After writing, read the output file back and check the records are in order, \
+        by timestamp and then by file and action type, exiting with an error at the first record that's out of place. \
+        This is a safety net for the merge sort, it's cheap compared to generating the log"
+    )]
+    pub verify_sort: bool,
+
+    #[arg(
+        long,
+        requires = "path",
+        value_name = "PATH",
+        help = "Make file paths relative to this directory instead of --path",
+        long_help = "Make file paths in the log relative to this directory instead of --path, \
+        so the root of the visualisation can be moved independently of where repositories are discovered. \
+        For example with '--path ~/code/acme/backend --relative-to ~/code' files appear under acme/backend/<repo>/. \
+        Every discovered repository must be inside this directory"
+    )]
+    pub relative_to: Option<String>,
+
+    #[arg(
+        long,
+        help = "Log every warning as it happens instead of just a summary",
+        long_help = "Log every warning as it happens, e.g. each commit that failed to diff. \
+        By default problems that can happen many times are counted and a short summary is logged at the end of the run"
+    )]
+    pub verbose: bool,
+
+    #[arg(
+        long,
+        help = "Record how many files each commit changed on its records",
+        long_help = "Record the total number of files each commit changed (before any filters) \
+        as a changeset_size field on every record from that commit, so consumers can weight busy commits. \
+        The field survives the merge sort and is read back by --from-json, \
+        it's only written by JSON output, Gource's pipe format has no column for it"
+    )]
+    pub emit_changeset_size: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "use_merge_sort",
+        help = "Switch to the merge sort automatically if the logs use too much memory",
+        long_help = "Keep the logs in memory like normal, but if they grow past --memory-limit while reading the repositories, \
+        write everything collected so far to the temporary file and carry on with the merge sort, as if --use-merge-sort had been given. \
+        Uses the same --sort-chunk-size and --temp-file-location options as --use-merge-sort"
+    )]
+    pub auto_merge_sort: bool,
+
+    #[arg(
+        long,
+        requires = "auto_merge_sort",
+        value_name = "MB",
+        help = "How much memory the logs can use before --auto-merge-sort switches to disk [default: 4096]",
+        long_help = "How much memory in MB the logs can use before --auto-merge-sort switches to the merge sort [default: 4096]. \
+        This only counts the log records themselves, the process as a whole will use more than this"
+    )]
+    pub memory_limit: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write a summary of the events in the log to a file",
+        long_help = "Write a summary of the events in the log to a file, \
+        with the number of events for each file extension and each author (after aliases are applied), busiest first. \
+        Use --stats-format json for something machine readable"
+    )]
+    pub stats: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = StatsFormat::Text,
+        requires = "stats",
+        help = "The format of the --stats file"
+    )]
+    pub stats_format: StatsFormat,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Leave out the N most recent commits of each repository",
+        long_help = "Leave out the N most recent commits of each repository (newest by commit time), \
+        e.g. to ignore release and tagging commits at the tip, or today's work in progress, so the output is stable"
+    )]
+    pub skip_recent: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "TEXT",
+        help = "Start the output with a '#' comment header, describing how it was generated, then this text",
+        long_help = "Start the output with a '#' comment header, with lines saying which version of rource generated the log, \
+        when, and with what command, followed by this text. Each line of the text becomes its own '#' comment line. \
+        Gource ignores lines it can't parse so the header is harmless there. Without this nothing is written before the first record, \
+        so runs over the same history give identical output"
+    )]
+    pub header_comment: Option<String>,
+
+    #[arg(
+        long,
+        help = "Don't write the '#' comment header at the top of the output",
+        long_help = "Don't write the '#' comment header at the top of the output, \
+        e.g. to turn off a --header-comment given in a config file or a job for a consumer of the log that objects to it"
+    )]
+    pub no_header: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Only keep the first N events in each directory",
+        long_help = "Only keep the first N events in each directory, any later events in that directory are dropped. \
+        This deliberately changes how busy each directory looks, so one huge generated directory can't dominate the visualisation. \
+        Use --dir-level to choose what counts as a directory"
+    )]
+    pub max_events_per_dir: Option<NonZeroU64>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DirectoryLevel::Top,
+        requires = "max_events_per_dir",
+        help = "What counts as a directory for --max-events-per-dir",
+        long_help = "What counts as a directory for --max-events-per-dir, \
+        'top' caps everything under each top-level entry (e.g. each repository), \
+        'full' caps each directory separately by its full path, not including its subdirectories"
+    )]
+    pub dir_level: DirectoryLevel,
+
+    #[arg(
+        long,
+        conflicts_with = "repo_concurrency",
+        help = "Read repositories and commits one at a time, oldest commit first",
+        long_help = "Read one repository at a time, streaming each repository's commits oldest first on a single thread \
+        instead of collecting every commit up front and diffing them in parallel. \
+        This is much slower on a multi-core machine, but commits are always processed in the same order and less memory is used. \
+        The output is the same either way"
+    )]
+    pub sequential: bool,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "output",
+        help = "Write a separate Gource log for each author into a directory",
+        long_help = "Write a separate Gource log for each author (after aliases are applied) into a directory, \
+        e.g. for per-contributor highlight reels. Each file is a complete log that Gource can open on its own, \
+        named after the author with any characters that aren't safe in a filename replaced by '_'"
+    )]
+    pub split_by_author: Option<String>,
+
+    #[arg(
+        long,
+        help = "Don't count commits and records up front for the progress bars",
+        long_help = "Don't count the commits in each repository, or the records in the merge sort's temporary file, before processing them. \
+        Counting needs an extra pass over each repository and the temporary file, which adds up on huge repositories. \
+        The progress bars become counters without a percentage or ETA"
+    )]
+    pub no_progress_count: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Attribute changes to the team that owns each directory",
+        long_help = "Attribute changes to the team that owns each directory instead of the author, \
+        using a file of <DIRECTORY>::<OWNER> lines, e.g. 'backend/api::API Team'. \
+        Directories are paths as they appear in the log (including the repository) and the longest matching directory wins, \
+        files that aren't under any listed directory keep their author. Blank lines and lines starting with '#' are ignored"
+    )]
+    pub owner_map: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DATE",
+        value_parser = crate::validation::parse_date,
+        help = "Skip repositories with no commits since DATE",
+        long_help = "Skip repositories whose HEAD commit is older than DATE, given as YYYY-MM-DD (midnight UTC), an RFC 3339 date and time or unix time, \
+        so dormant repositories aren't read at all. \
+        Only the HEAD commit is checked, older commits in active repositories are still included"
+    )]
+    pub repo_active_since: Option<i64>,
+
+    #[arg(
+        long,
+        value_name = "DATE",
+        value_parser = crate::validation::parse_date,
+        conflicts_with = "snapshot_ref",
+        help = "Only include commits made on or after DATE",
+        long_help = "Only include commits made on or after DATE, given as YYYY-MM-DD (midnight UTC), an RFC 3339 date and time or unix time, \
+        using the --date-source date the same as the log. \
+        With the libgit2 backend the history is sorted newest first and with committer dates reading stops at the first commit older than DATE, \
+        so only the part of a long history that's wanted is read. \
+        With --sequential or the git-cli backend every commit is still read and the older ones are left out, \
+        since git log's own --since gives up at the first older commit it walks to and skewed clocks would drop newer ones after it"
+    )]
+    pub since: Option<i64>,
+
+    #[arg(
+        long,
+        value_name = "DATE",
+        value_parser = crate::validation::parse_date,
+        conflicts_with = "snapshot_ref",
+        help = "Only include commits made on or before DATE",
+        long_help = "Only include commits made on or before DATE, given as YYYY-MM-DD (midnight UTC), an RFC 3339 date and time or unix time, \
+        so a date on its own ends at the start of that day, give the next day to include all of it. \
+        Can be combined with --since for a window, which must not end before it starts. \
+        --skip-recent counts from the newest commit, before any are left out for being too new"
+    )]
+    pub until: Option<i64>,
+
+    #[arg(
+        long,
+        help = "Delete files at the end of the log that aren't in HEAD",
+        long_help = "A final-state reconciliation pass: once a repository has been read, \
+        any file whose last event in the log isn't a delete but which doesn't exist in HEAD gets a delete event, \
+        at the time of the HEAD commit and attributed to its author. \
+        This removes the ghost files left behind when a delete was filtered out (e.g. by --max-changeset-size), \
+        so the end of the visualisation matches the current tree"
+    )]
+    pub emit_deletes_for_final_state: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "from_json",
+        help = "Read the repositories and options for the run from a JSON job spec",
+        long_help = "Read the repositories and options for the run from a JSON job spec, for CI systems that generate them. \
+        Repositories can have their own prefix, time offset (in seconds) and aliases, which can't be expressed on the command line. \
+        Settings in the job override the same options on the command line, see the readme for the format"
+    )]
+    pub job: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Read default options from this TOML file",
+        long_help = "Read default options from this TOML file instead of .rource.toml in the current directory \
+        or ~/.config/rource/config.toml (under $XDG_CONFIG_HOME when it's set), whichever is found first. \
+        Keys are the long option names with underscores, e.g. 'use_merge_sort = true' or 'alias = [\"a::b\"]'. \
+        Options given on the command line replace the file's, a --job file replaces both"
+    )]
+    pub config: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "config",
+        help = "Don't read options from a config file",
+        long_help = "Don't read options from .rource.toml or ~/.config/rource/config.toml, e.g. to turn off a flag the file sets"
+    )]
+    pub no_config: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the number of events in each period of time to a CSV file",
+        long_help = "Write the number of events in each period of time to a CSV file, \
+        e.g. to draw an activity graph alongside the visualisation. \
+        Each row is the start of a period in unix time and the number of events in it, \
+        periods with no events are left out. The length of each period is set with --activity-bucket"
+    )]
+    pub activity_csv: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ActivityBucket::Day,
+        requires = "activity_csv",
+        help = "How much time each row of the --activity-csv file covers",
+        long_help = "How much time each row of the --activity-csv file covers, periods start at midnight UTC"
+    )]
+    pub activity_bucket: ActivityBucket,
+
+    #[arg(
+        long,
+        help = "Detect copies and show them as added rather than modified",
+        long_help = "Detect copies and show them as added at their new path, rather than modified. \
+        Like 'git log --find-copies' a file is only found to be a copy of one changed in the same commit, \
+        and renames are detected too. Detection makes reading slower, without it a copy is already just an added file"
+    )]
+    pub copy_as_added: bool,
+
+    #[arg(
+        long,
+        value_name = "GB",
+        default_value_t = 1.0,
+        help = "Warn if the log is larger than this many GB before writing it",
+        long_help = "Warn if the log is larger than this many GB before writing it, suggesting ways to make it smaller. \
+        With the merge sort this is the size of the temporary file, otherwise it's an estimate of the memory the records are using"
+    )]
+    pub warn_output_size: f64,
+
+    #[arg(
+        long,
+        conflicts_with = "verbose",
+        help = "Don't warn about the size of the log or summarise the problems found",
+        long_help = "Don't warn about the size of the log or summarise the repositories and commits that were skipped"
+    )]
+    pub no_warnings: bool,
+
+    #[arg(
+        long,
+        help = "Read per-repository options from the [rource] section of each repository's git config",
+        long_help = "Read per-repository options from the [rource] section of each repository's own git config (.git/config): \
+        rource.prefix replaces the repository's path in the log, \
+        rource.alias adds an <A>::<B> alias for that repository and rource.exclude leaves out files matching a glob, \
+        both can be given more than once. \
+        The command line takes precedence, then a --job file, then the git config, then the built-in defaults"
+    )]
+    pub use_git_config: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Only read every Nth commit of each repository, for quick previews",
+        long_help = "Only read every Nth commit of each repository in time order, \
+        so a preview covers the whole history but takes a fraction of the time. \
+        This is lossy, files changed by the commits in between are missing or appear late, \
+        so it's meant for trying out Gource settings rather than final renders"
+    )]
+    pub sample_commits: Option<NonZeroUsize>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "from_json",
+        help = "Write every author to a file as alias stubs instead of generating the log",
+        long_help = "Write every distinct author in the repositories to a file instead of generating the log, \
+        as <NAME>::<NAME> alias stubs ready to have the right hand side edited. \
+        Only the commit metadata is read, so this is much faster than generating the log"
+    )]
+    pub dump_authors: Option<String>,
+
+    #[arg(
+        long,
+        requires = "dump_authors",
+        help = "List each author as 'Name <email>' in the --dump-authors file",
+        long_help = "List each distinct name and email as 'Name <email>' in the --dump-authors file, rather than alias stubs, \
+        to help find people who commit under more than one identity"
+    )]
+    pub use_email: bool,
+
+    #[arg(
+        long,
+        help = "Fail if a repository looks corrupt, rather than warning",
+        long_help = "Fail if a repository looks corrupt, rather than warning and carrying on with an unreliable log. \
+        A repository looks corrupt when many commits in a row can't be read or diffed, \
+        a single bad commit is still skipped"
+    )]
+    pub strict: bool,
+
+    #[arg(
+        long,
+        help = "Keep commits made in the same second in history order",
+        long_help = "Keep commits made in the same second in history order, parents before their children, \
+        rather than sorting their changes by file name. \
+        Gource timestamps are whole seconds, so this only changes the order of the lines within each second, \
+        which Gource plays back in the order they appear"
+    )]
+    pub preserve_commit_order: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = RepositoryOrder::Name,
+        help = "The order to read repositories in",
+        long_help = "The order to read repositories in, so runs are reproducible on filesystems that list directories in different orders. \
+        The log is sorted by time either way, this sets the order repositories are processed and reported in, \
+        which is most visible with --sequential. Repositories listed in a --job file are sorted too"
+    )]
+    pub sort_repos: RepositoryOrder,
+
+    #[arg(
+        long,
+        conflicts_with = "relative_to",
+        help = "Start every file with its repository's absolute path",
+        long_help = "Start every file with the absolute path of its repository's working directory, \
+        e.g. /home/me/code/backend/src/main.rs, instead of a path relative to --path. \
+        This replaces any per-repository prefix and is meant for tooling that maps the log back to files on disk, \
+        in Gource it makes for very long node labels and a deep, mostly empty tree above the repositories"
+    )]
+    pub absolute_paths: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "absolute_paths",
+        help = "Start the files of a repository at --path with the repository's name",
+        long_help = "Start the files of a repository that is --path itself with the repository's directory name, \
+        e.g. backend/src/main.rs instead of src/main.rs, the same as the repositories found inside --path. \
+        Use this when the logs of separate runs are combined, such as with --append-temp, \
+        so each repository gets its own subtree instead of their files colliding at the same paths. \
+        Repositories with a prefix from a job file keep it"
+    )]
+    pub prefix_repo_name: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["output", "split_by_author", "dump_authors", "finalize"],
+        help = "Add the logs to a temporary file instead of writing the Gource log",
+        long_help = "Add the logs to this temporary file instead of writing the Gource log, creating it if needed. \
+        Run rource once per repository or group of repositories with the same --append-temp file, \
+        then once with --finalize to sort everything and write a single log. \
+        Aliases given with --alias and the output options belong on the --finalize run, \
+        everything that affects reading the repositories belongs on the --append-temp runs. \
+        Runs appending to the same file mustn't overlap, to read repositories on several machines give each its own file \
+        and concatenate them, leaving out the header of all but the first"
+    )]
+    pub append_temp: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["path", "from_json", "job"],
+        help = "Sort a temporary file built with --append-temp and write the Gource log",
+        long_help = "Sort the records in a temporary file built by earlier --append-temp runs and write the Gource log, \
+        without reading any repositories. The file is checked to make sure it was written by a compatible version of rource, \
+        and it is left in place afterwards"
+    )]
+    pub finalize: Option<String>,
+
+    #[arg(
+        long,
+        help = "Show commits that didn't change any files",
+        long_help = "Show commits that didn't change any files, such as empty commits marking a release, \
+        as a change to a '(empty commit)' file at the root of their repository. \
+        By default these commits are left out of the log, as there is nothing for Gource to show"
+    )]
+    pub keep_empty_commits: bool,
+
+    #[arg(
+        long,
+        help = "Don't draw progress bars or log anything but warnings",
+        long_help = "Don't draw any progress bars, including the spinner while repositories are discovered, \
+        and only log warnings and errors. Useful in CI logs, where every redraw of a bar becomes a new line"
+    )]
+    pub quiet: bool,
+
+    #[arg(
+        long,
+        help = "Detect renames and show them as just the old path being deleted",
+        long_help = "Detect renamed files and show each rename as just the old path being deleted, \
+        without an add for the new path, which appears the next time it's modified. \
+        Useful when the new paths are covered by another log merged with this one. \
+        By default renames aren't detected, so a rename is shown as the old path being deleted and the new path added"
+    )]
+    pub rename_emit_delete_only: bool,
+
+    #[arg(
+        long,
+        overrides_with = "no_detect_renames",
+        help = "Detect renamed files, they're still shown as a delete and an add",
+        long_help = "Detect renamed files (at Git's usual 50% similarity) and show each move as the old path being deleted \
+        and the new path added at the same moment, which is how Gource shows a file moving. \
+        Without detection Git sees an unrelated delete and add, which look the same in the log, \
+        but a detected rename counts as one changed file for --emit-changeset-size rather than two. \
+        Detection compares the contents of every added and deleted file, so it costs CPU on large commits"
+    )]
+    pub detect_renames: bool,
+
+    #[arg(
+        long,
+        overrides_with = "detect_renames",
+        conflicts_with = "rename_emit_delete_only",
+        help = "Don't detect renamed files, the default",
+        long_help = "Don't detect renamed files, the default. Use this to turn off a --detect-renames from a config file"
+    )]
+    pub no_detect_renames: bool,
+
+    #[arg(
+        long,
+        help = "Draw progress bars with this indicatif template",
+        long_help = "Draw the progress bars with this indicatif template instead of the default \
+        '{prefix:<30!.cyan.bold} {percent:>3}% [{bar:50!}] {msg} {pos}/{len} @ {per_sec} ETA:{eta}', \
+        e.g. '{prefix} {percent}%' for a narrow terminal. See https://docs.rs/indicatif for the available fields. \
+        The template is checked before anything else is done"
+    )]
+    pub progress_template: Option<String>,
+
+    #[arg(
+        long,
+        help = "Draw spinners with this indicatif template",
+        long_help = "Draw the spinners shown while repositories are discovered and opened, and while the log is sorted, \
+        with this indicatif template instead of the default '{prefix:<30.cyan.bold} {spinner} {wide_msg}'. \
+        The template is checked before anything else is done"
+    )]
+    pub spinner_template: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Never write more than N chunk files during the merge sort",
+        long_help = "Never write more than N chunk files during the merge sort, for filesystems with few inodes or a low open file limit. \
+        Every chunk is merged in a single pass and kept open until the end, so the only way to have fewer files is bigger chunks: \
+        the records are counted up front and split evenly into N chunks, ignoring --sort-chunk-size. \
+        Each chunk is sorted in memory, so a small N with a large log needs a lot more RAM"
+    )]
+    pub max_temp_files: Option<NonZeroUsize>,
+
+    #[arg(
+        long,
+        help = "Join chains of directories that only contain one directory, e.g. com.example.app/",
+        long_help = "Join each directory that only ever contains a single directory to its child with a dot, \
+        so src/main/java/com/example/App.java becomes src/main.java.com.example/App.java when nothing else is ever in those directories, \
+        and Gource doesn't spend its layout on long chains of empty branches. \
+        This needs every path in the log before anything is written, with the merge sort that is an extra pass over the temporary file"
+    )]
+    pub flatten_single_child_dirs: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Identity::Author,
+        help = "Attribute commits to their author, committer or both",
+        long_help = "Attribute each commit's changes to its author, its committer, or both of them, \
+        to show who merged or rebased work alongside who wrote it. \
+        With both, every commit where the committer isn't the author is shown twice, once for each of them, \
+        so a history that's mostly merged by someone else has up to twice as many events. \
+        Aliases, --group-by-email-domain and --dump-authors apply to committers the same as authors"
+    )]
+    pub identity: Identity,
+
+    #[arg(
+        long,
+        conflicts_with = "identity",
+        help = "Attribute commits to their committer instead of their author, the same as --identity committer",
+        long_help = "Attribute each commit's changes to its committer instead of its author, the same as --identity committer, \
+        for workflows built on cherry-picks or applied patches where the person who integrated the code matters more. \
+        Aliases, --author-email and --group-by-email-domain apply to the committer as they would to the author"
+    )]
+    pub committer: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DateSource::Committer,
+        help = "Show each commit at its author or committer date",
+        long_help = "Show each commit at its author date, when the change was written, or its committer date, when it landed. \
+        The two differ for rebased and cherry-picked commits, which keep their author date. \
+        --since and --until use the same date. \
+        History is read newest first by committer date, so with author dates --since can't stop reading early. \
+        The default is the committer date because that's the date rource has always used, so existing logs don't change"
+    )]
+    pub date_source: DateSource,
+
+    #[arg(
+        long,
+        help = "Drop repeated events for the same user, file and action within a commit",
+        long_help = "Drop repeated events for the same user, file and action within a single commit, \
+        comparing users after aliases are applied. Aliasing several people to one name, or --identity both \
+        with an author and committer aliased together, can otherwise show the same change more than once at the same moment. \
+        Only events from the same commit are compared, the same change in two commits is kept"
+    )]
+    pub dedupe_within_commit: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["append_temp", "dump_authors"],
+        help = "Also write every record as newline delimited JSON to this file",
+        long_help = "Also write every record as newline delimited JSON to this file, in the same pass as the Gource log, \
+        so a JSON archive doesn't need a second walk of every repository. Records are written after aliases and every other filter, \
+        including --emit-changeset-size and --preserve-commit-order fields, and the file can be read back with --from-json. \
+        Works alongside --output, stdout or --split-by-author"
+    )]
+    pub json_output: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Gource,
+        conflicts_with_all = ["split_by_author", "verify_sort"],
+        help = "Write the log in Gource's format or as JSON",
+        long_help = "Write the log to --output or stdout in Gource's format, or as newline delimited JSON for other tools. \
+        With json each record is an object on its own line with timestamp, username, type, file and changeset_size keys \
+        (plus sequence and color), so the log is streamed rather than kept in memory as one big array, \
+        and it can be read back with --from-json. No header is written and --field-order doesn't apply. \
+        Use --json-output instead to write both formats at once"
+    )]
+    pub format: OutputFormat,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Compress the log, by default it's gzipped when --output ends in .gz",
+        long_help = "Compress the log written to --output or stdout, e.g. '--compress gzip | ssh host ...'. \
+        Without this option the log is gzipped when --output ends in .gz, and --json-output the same, \
+        so '--compress none' writes a plain log whatever it's called. Gource can't read a compressed log, \
+        decompress it with 'gunzip' or 'zcat gource.log.gz | gource --log-format custom -'"
+    )]
+    pub compress: Option<OutputCompression>,
+
+    #[arg(
+        long,
+        value_name = "REF",
+        conflicts_with_all = ["from_json", "emit_deletes_for_final_state"],
+        help = "Add every file in a branch, tag or commit at once instead of reading the history",
+        long_help = "Instead of reading each repository's history, add every file in the tree of a branch, tag or commit \
+        as a single burst of Added events at the time of that commit, attributed to its author. \
+        This shows the structure of the code at that point rather than how it got there, e.g. to compare two releases: \
+        run once per tag with a different time_offset in a --job file and concatenate the logs. \
+        Repositories that don't have the ref are skipped with a warning. Path filters and exclusions still apply"
+    )]
+    pub snapshot_ref: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "recursive",
+        visible_alias = "depth",
+        help = "Don't search more than N levels of directories below <PATH> for repositories",
+        long_help = "Don't search more than N levels of directories below <PATH> for repositories with --recursive, \
+        so very deep trees (e.g. a home directory full of node_modules) don't take forever to scan. \
+        0 only checks <PATH> itself, 1 also checks the directories directly inside it, and so on. \
+        Without it the search goes as deep as the directories do"
+    )]
+    pub max_depth: Option<usize>,
+
+    #[arg(
+        short = 'k',
+        long,
+        help = "Leave out repositories that fail to read instead of stopping",
+        long_help = "Leave out repositories that fail to read, e.g. one with no commits or a broken HEAD, \
+        instead of stopping the whole run, like make -k. The number of repositories left out is reported at the end, \
+        with --verbose the reason for each one is too. Any commits read before a repository failed stay in the log. \
+        Combined with --strict, repositories that look corrupt are left out rather than failing the run"
+    )]
+    pub keep_going: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = MergeAttribution::Merger,
+        help = "Attribute merge commits to whoever merged them or the author of the merged work",
+        long_help = "Attribute the changes in merge commits to the author of the merge, or to the author of the work that was merged in, \
+        the most recent commit on the merge's second parent. The whole merge goes to that one person, \
+        there's no way to tell which of the merged commits each file came from. \
+        A merge's changes are the difference from its first parent, everything the merged branch brought in. \
+        Fast-forward merges have no merge commit and are attributed to their authors either way"
+    )]
+    pub merge_attribution: MergeAttribution,
+
+    #[arg(
+        long,
+        value_name = "OID",
+        help = "Leave out a commit, can be given multiple times",
+        long_help = "Leave out the commit with this id, full or abbreviated to at least 4 characters, e.g. a bad import or a reverted mistake. \
+        Can be given multiple times, each id is looked up in every repository and ids a repository doesn't have are ignored. \
+        An abbreviation that matches more than one commit in a repository is ignored with a warning. \
+        The commit's changes are simply missing, so a file it added first appears the next time it's modified"
+    )]
+    pub exclude_commit: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Leave out the commits listed in a file, one id per line",
+        long_help = "Leave out the commits listed in a file, one full or abbreviated id per line, the same as giving each to --exclude-commit. \
+        Blank lines and lines starting with '#' are ignored"
+    )]
+    pub exclude_commits_file: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Only read the commits of this author, can be given multiple times",
+        long_help = "Only read the commits whose author has this name, can be given multiple times to read several authors' commits. \
+        Names are matched exactly after the .mailmap but before any aliases, so use the name 'git log --use-mailmap' shows. \
+        Commits are matched by their author whatever --identity says, and are left out before they're diffed. \
+        The co-authors of the commits that are read are still shown"
+    )]
+    pub author: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Leave out the commits of this author, can be given multiple times",
+        long_help = "Leave out the commits whose author has this name, e.g. a bot, matched the same way as --author. \
+        An author given to both --author and --exclude-author is left out"
+    )]
+    pub exclude_author: Vec<String>,
+
+    #[arg(long, help = "Match --author and --exclude-author ignoring case")]
+    pub author_ignore_case: bool,
+
+    #[arg(
+        long,
+        help = "Leave out merge commits",
+        long_help = "Leave out merge commits, the same as 'git log --no-merges'. \
+        A merge is shown as the changes it brought in compared to its first parent, which for a long-lived branch \
+        can be a huge burst of changes that were already shown when they were committed on the branch. \
+        The merged branch's commits are still read, so only changes made in the merge itself, like conflict resolutions, go missing. \
+        Merges are left out before they're diffed"
+    )]
+    pub no_merges: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "no_merges",
+        help = "Only read merge commits",
+        long_help = "Only read merge commits, the same as 'git log --merges', to see a project at the level of the \
+        branches and pull requests that landed rather than every commit. Combine with --merge-attribution first-parent-author \
+        to give each merge's changes to whoever wrote the branch"
+    )]
+    pub merges_only: bool,
+
+    #[arg(
+        long,
+        help = "Log why each file event is left out of the log",
+        long_help = "Log a line for every file event that's left out, naming the file, the commit and the filter responsible, \
+        to find out why a file is missing from the visualisation. The filters are checked in a fixed order and only the first \
+        that matches is reported, see the readme. This can be a lot of output, it's meant for debugging a filter setup. \
+        Nothing is logged when the log is written to stdout, use --output"
+    )]
+    pub explain_filters: bool,
+
+    #[arg(
+        long,
+        help = "Add the date and time to every record, for reading the log by eye",
+        long_help = "Add the UTC date and time in ISO 8601 to every record, e.g. 2024-01-31T09:30:00Z, so the log can be read \
+        without converting timestamps by hand. In the Gource log it's written as a '# <DATE>' comment line before each record, \
+        which Gource skips, so the records keep their usual columns and the log can still be given to Gource. \
+        In JSON output each record gets a date field"
+    )]
+    pub debug_dates: bool,
+
+    #[arg(
+        long,
+        help = "Treat names that only differ by case as the same author",
+        long_help = "Treat author names that only differ by case, e.g. 'John Doe' and 'john doe', as the same person, \
+        and match aliases ignoring case too. Each person is written with the casing of their earliest commit in the log, \
+        so which one is picked can change if the history is rewritten or filtered differently. \
+        Use an alias to choose the casing yourself"
+    )]
+    pub case_insensitive_authors: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["dump_authors", "preview"],
+        help = "Check the arguments and repositories, then exit without reading any commits",
+        long_help = "Check the arguments and repositories, then exit without reading any commits or writing anything. \
+        Aliases, filters, the job file and other options are validated, repositories are discovered \
+        and checked the same way as a normal run. Exits with 0 if the run would start, \
+        otherwise with the same exit code the run would have failed with, see the readme"
+    )]
+    pub dry_validate: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "LIST",
+        value_delimiter = ',',
+        default_value = "timestamp,username,type,file",
+        conflicts_with = "preview",
+        help = "The order of the columns in the log, for tools other than Gource",
+        long_help = "The order of the columns in the log, a comma separated list of timestamp, username, type and file \
+        that must name each of them once, e.g. 'username,timestamp,type,file'. The default is the order Gource expects, \
+        any other order is for feeding the log to other tools, Gource can't read it. \
+        Applies to --split-by-author logs too, --json-output records are unaffected"
+    )]
+    pub field_order: Vec<LogField>,
+
+    #[arg(
+        long,
+        conflicts_with = "from_json",
+        help = "Add a marker file to each repository at the time of its first commit",
+        long_help = "Add a marker file named .rource-repo at the root of each repository, \
+        at the time of the earliest commit that made it into the log and attributed to its author, \
+        so each repository visibly joins the combined timeline. Hide the markers in Gource with --file-filter '\\.rource-repo$'"
+    )]
+    pub emit_repo_markers: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "snapshot_ref",
+        help = "Read each repository's default branch instead of whatever is checked out",
+        long_help = "Read each repository's default branch instead of whatever is checked out, \
+        so a set of repositories left on feature branches or detached HEADs is read consistently. \
+        The default branch is the one origin's HEAD points at, preferring the local branch of the same name, \
+        otherwise the local branch named by init.defaultBranch. \
+        Repositories where neither can be found are read from HEAD as usual. \
+        Files deleted by --emit-deletes-for-final-state are the ones missing from this branch"
+    )]
+    pub default_branch: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = ["snapshot_ref", "default_branch"],
+        help = "Read this local branch of each repository instead of whatever is checked out",
+        long_help = "Read the history of this local branch in each repository instead of whatever is checked out, \
+        e.g. when CI leaves HEAD on a feature branch but the canonical history is on main. \
+        A repository that doesn't have the branch is an error rather than falling back to HEAD, \
+        use --keep-going to leave those repositories out instead. \
+        Files deleted by --emit-deletes-for-final-state are the ones missing from this branch"
+    )]
+    pub branch: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["snapshot_ref", "branch"],
+        help = "Read the commits on every local branch, not just the one checked out",
+        long_help = "Read the commits on every local branch as well as the ones reachable from HEAD, \
+        so work that only exists on unmerged branches shows up too. Commits on more than one branch are only read once. \
+        Remote-tracking branches and tags aren't read. \
+        --emit-deletes-for-final-state still reconciles against HEAD, or the --default-branch"
+    )]
+    pub all_refs: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["snapshot_ref", "all_refs"],
+        help = "Only read the mainline history, following the first parent of each merge",
+        long_help = "Only read the mainline history, following the first parent of each merge like 'git log --first-parent', \
+        so the commits on merged branches are left out and the timeline doesn't jump back to when they were written. \
+        Each merge still brings in the branch's changes, compared to its first parent. \
+        Can't be used with --all-refs, which reads the branches this leaves out"
+    )]
+    pub first_parent: bool,
+
+    #[arg(
+        long,
+        value_name = "SHA",
+        conflicts_with = "snapshot_ref",
+        value_parser = crate::validation::parse_commit_sha,
+        help = "Only read the commits made after this one, leaving out it and everything before it",
+        long_help = "Only read the commits made after this one, leaving out the commit and every commit it was built on, \
+        like 'git log <SHA>..HEAD'. Exact where --since is thrown off by clocks that drift. \
+        Takes a full or abbreviated SHA of at least 7 hex digits, and every repository must have the commit, \
+        use --keep-going to leave out the ones that don't"
+    )]
+    pub since_commit: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SHA",
+        conflicts_with_all = ["snapshot_ref", "default_branch", "branch", "all_refs"],
+        value_parser = crate::validation::parse_commit_sha,
+        help = "Read the history back from this commit instead of whatever is checked out",
+        long_help = "Read the history back from this commit instead of whatever is checked out, \
+        so nothing made after it is read. Takes a full or abbreviated SHA of at least 7 hex digits, \
+        and every repository must have the commit, use --keep-going to leave out the ones that don't. \
+        Files deleted by --emit-deletes-for-final-state are the ones missing from this commit"
+    )]
+    pub until_commit: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "split_by_author",
+        help = "Write the --split-by-author logs into a tar.gz archive instead of a directory",
+        long_help = "Write the --split-by-author logs into a tar.gz archive instead of a directory, \
+        to hand them out as a single file. Each entry is named <DIR>/<AUTHOR>.log, where DIR is the last part of the \
+        --split-by-author path and AUTHOR is named the same way as the loose files, so extracting it gives the same directory. \
+        Nothing is written to the directory itself. Each author's log is written to a file in the system temporary directory \
+        (TMPDIR) as it's read and copied into the archive at the end, that directory is removed afterwards even if the run fails. \
+        The logs can't be streamed straight into the archive without it, a tar entry starts with its size \
+        and every author's records are spread through the whole log, so no entry is known to be complete until the end. \
+        Only --split-by-author logs can be archived"
+    )]
+    pub archive: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ColorBy::None,
+        help = "Add Gource's colour column to every record, based on the author or the file extension",
+        long_help = "Add Gource's optional fifth column to every record, a hex colour that Gource uses for the file. \
+        'user' gives each author (after aliases) their own colour and 'extension' gives each file extension its own, \
+        the colours come from a hash of the name so they're the same in every log. \
+        'none' leaves the column out, so the log has the usual four columns"
+    )]
+    pub color_by: ColorBy,
 }
@@ -0,0 +1,210 @@
+use crate::cli::ClapArguments;
+use crate::consts::{CONFIG_FILENAME, USER_CONFIG_PATH};
+use anyhow::{anyhow, bail, Context};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Options that only make sense on the command line
+const COMMAND_LINE_ONLY: [&str; 4] = ["config", "no_config", "help", "version"];
+
+/// Parse the command line, with any options it doesn't give taken from the config file.
+/// The file is `--config`, otherwise `.rource.toml` in the current directory, otherwise the user's config
+pub fn parse_arguments() -> Result<ClapArguments, clap::Error> {
+    let command_line = std::env::args_os().collect::<Vec<_>>();
+
+    // Only --config and --no-config are needed to find the file, anything else wrong
+    // with the command line is reported once the file's options have been added
+    let Ok(matches) = ClapArguments::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&command_line)
+    else {
+        return ClapArguments::try_parse_from(&command_line);
+    };
+    // Arguments after an error have no defaults, so the flag may not be set either way
+    if matches!(matches.try_get_one::<bool>("no_config"), Ok(Some(true))) {
+        return ClapArguments::try_parse_from(&command_line);
+    }
+    let explicit = matches.get_one::<String>("config");
+    let Some(path) = explicit
+        .map(|path| PathBuf::from(&*shellexpand::tilde(path)))
+        .or_else(find_config)
+    else {
+        return ClapArguments::try_parse_from(&command_line);
+    };
+
+    let given = ClapArguments::command()
+        .get_arguments()
+        .filter(|arg| {
+            matches.value_source(arg.get_id().as_str())
+                == Some(clap::parser::ValueSource::CommandLine)
+        })
+        .map(|arg| arg.get_id().to_string())
+        .collect::<Vec<_>>();
+    let file_arguments = read_config(&path, &given).map_err(|e| {
+        ClapArguments::command().error(
+            ErrorKind::InvalidValue,
+            format!("Invalid config {}: {e:#}", path.display()),
+        )
+    })?;
+
+    // Options from the file go before the command line, after the program name
+    let mut arguments = command_line.into_iter();
+    ClapArguments::try_parse_from(
+        arguments
+            .next()
+            .into_iter()
+            .chain(file_arguments)
+            .chain(arguments),
+    )
+}
+
+/// Can't the option be used with one of the options given on the command line? Conflicts are declared on
+/// one side of the pair, so both sides are checked
+fn conflicts_with_given(command: &clap::Command, arg: &clap::Arg, given: &[String]) -> bool {
+    let named = |id: &clap::Id| given.iter().any(|given| given == id.as_str());
+    command
+        .get_arg_conflicts_with(arg)
+        .iter()
+        .any(|other| named(other.get_id()))
+        || command
+            .get_arguments()
+            .filter(|other| named(other.get_id()))
+            .any(|other| {
+                command
+                    .get_arg_conflicts_with(other)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == arg.get_id())
+            })
+}
+
+/// `.rource.toml` in the current directory, otherwise `config.toml` in the user's config directory
+fn find_config() -> Option<PathBuf> {
+    let local = PathBuf::from(CONFIG_FILENAME);
+    if local.is_file() {
+        return Some(local);
+    }
+    let user = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|home| !home.is_empty())
+        .map_or_else(
+            || PathBuf::from(&*shellexpand::tilde("~/.config")),
+            PathBuf::from,
+        )
+        .join(USER_CONFIG_PATH);
+    user.is_file().then_some(user)
+}
+
+/// Turn the file's options into command line arguments, skipping the ones given on the command line
+/// and the ones that can't be used with an option given on the command line, so the command line wins.
+/// Keys are the same as the options with underscores, e.g. `use_merge_sort = true` for --use-merge-sort
+fn read_config(path: &Path, given: &[String]) -> anyhow::Result<Vec<OsString>> {
+    let contents =
+        fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+    let table = contents.parse::<toml::Table>()?;
+    let command = ClapArguments::command();
+
+    let mut arguments = Vec::new();
+    for (key, value) in table {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_id().as_str() == key)
+        else {
+            bail!("{key} is not an option");
+        };
+        if COMMAND_LINE_ONLY.contains(&key.as_str()) {
+            bail!("{key} can only be given on the command line");
+        }
+        if given.contains(&key) || conflicts_with_given(&command, arg, given) {
+            continue;
+        }
+        let long = arg
+            .get_long()
+            .ok_or_else(|| anyhow!("{key} can only be given on the command line"))?;
+
+        if !arg.get_action().takes_values() {
+            let toml::Value::Boolean(enabled) = value else {
+                bail!("{key} must be true or false");
+            };
+            if enabled {
+                arguments.push(format!("--{long}").into());
+            }
+            continue;
+        }
+        let values = if let toml::Value::Array(values) = value {
+            values
+        } else {
+            vec![value]
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                toml::Value::Datetime(value) => value.to_string(),
+                toml::Value::Array(_) | toml::Value::Table(_) => {
+                    bail!("{key} must be a string, number or a list of them")
+                }
+            };
+            arguments.push(format!("--{long}={value}").into());
+        }
+    }
+    Ok(arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_config;
+    use crate::cli::ClapArguments;
+    use clap::Parser;
+    use std::ffi::OsString;
+
+    /// Parse a command line the way `parse_arguments` does, with this config file and `--path .`
+    fn parse(config: &str, command_line: &[&str], given: &[&str]) -> anyhow::Result<ClapArguments> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, config)?;
+        let given = given.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let file_arguments = read_config(&path, &given)?;
+        Ok(ClapArguments::try_parse_from(
+            std::iter::once(OsString::from("rource"))
+                .chain(file_arguments)
+                .chain(
+                    ["--path", "."]
+                        .into_iter()
+                        .chain(command_line.iter().copied())
+                        .map(OsString::from),
+                ),
+        )?)
+    }
+
+    #[test]
+    fn the_command_line_wins_over_a_conflicting_file_option() -> anyhow::Result<()> {
+        let config = "output = 'gource.log'\nuse_merge_sort = true";
+        let args = parse(
+            config,
+            &["--split-by-author", "authors"],
+            &["split_by_author"],
+        )?;
+        assert_eq!(args.output, None);
+        assert_eq!(args.split_by_author.as_deref(), Some("authors"));
+        assert!(args.use_merge_sort);
+
+        let args = parse(config, &[], &[])?;
+        assert_eq!(args.output.as_deref(), Some("gource.log"));
+        Ok(())
+    }
+
+    #[test]
+    fn no_header_turns_off_a_header_comment_from_the_file() -> anyhow::Result<()> {
+        let args = parse(
+            r#"header_comment = "Nightly""#,
+            &["--no-header"],
+            &["no_header"],
+        )?;
+        assert!(crate::build_header(&args, None).is_empty());
+        Ok(())
+    }
+}
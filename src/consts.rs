@@ -1,5 +1,66 @@
 pub const DEFAULT_PROGRESS_STYLE: &str =
     "{prefix:<30!.cyan.bold} {percent:>3}% [{bar:50!}] {msg} {pos}/{len} @ {per_sec} ETA:{eta}";
+/// Like the progress style, but for when we don't know how many items there are
+pub const DEFAULT_COUNTER_STYLE: &str = "{prefix:<30!.cyan.bold} {spinner} {msg} {pos} @ {per_sec}";
 pub const DEFAULT_SPINNER_STYLE: &str = "{prefix:<30.cyan.bold} {spinner} {wide_msg}";
 pub const DEFAULT_SPINNER_TICK_STYLE: &str = "-\\|/";
 pub const TEMPORARY_LOG_FILENAME: &str = "rource-temp.bin";
+/// Read from the current directory when --config isn't given
+pub const CONFIG_FILENAME: &str = ".rource.toml";
+/// Under the user's config directory, used when there's no `CONFIG_FILENAME` in the current directory
+pub const USER_CONFIG_PATH: &str = "rource/config.toml";
+/// Every temporary file starts with this, followed by `TEMP_FILE_VERSION` as a little-endian u16
+pub const TEMP_FILE_MAGIC: &[u8; 6] = b"ROURCE";
+/// Bump this whenever the way records are written to the temporary file changes
+pub const TEMP_FILE_VERSION: u16 = 3;
+/// Used by --auto-merge-sort when --memory-limit isn't given
+pub const DEFAULT_MEMORY_LIMIT_MB: u64 = 4096;
+pub const PROGRESS_SOCKET_INTERVAL_MS: u64 = 250;
+/// Commit header for the git CLI backend, fields are separated by 0x1F and the header starts with 0x1E
+pub const GIT_CLI_LOG_FORMAT: &str =
+    "--format=%x1e%H%x1f%ct%x1f%an%x1f%P%x1f%ae%x1f%cn%x1f%ce%x1f%at%x1f%(trailers:key=Co-authored-by,valueonly,unfold,separator=%x1d)";
+/// The same as `GIT_CLI_LOG_FORMAT` with the names and emails mapped through the repository's .mailmap
+pub const GIT_CLI_MAILMAP_LOG_FORMAT: &str =
+    "--format=%x1e%H%x1f%ct%x1f%aN%x1f%P%x1f%aE%x1f%cN%x1f%cE%x1f%at%x1f%(trailers:key=Co-authored-by,valueonly,unfold,separator=%x1d)";
+/// The trailer that names the other authors of a commit, the key is matched ignoring case
+pub const CO_AUTHOR_TRAILER: &str = "Co-authored-by";
+/// This many commits in a row failing to read means the repository is probably corrupt
+pub const CORRUPT_REPOSITORY_FAILURES: usize = 20;
+/// Appended to the output filename to get the checkpoint sidecar file
+pub const CHECKPOINT_SUFFIX: &str = ".checkpoint";
+/// The output is written to this file alongside it, then renamed over it once complete
+pub const PARTIAL_OUTPUT_SUFFIX: &str = ".partial";
+/// More authors than this and --split-by-author starts closing and reopening files
+pub const MAX_OPEN_SPLIT_FILES: usize = 256;
+/// A name or email used with at least this many of the other is reported as a fragmented identity
+pub const IDENTITY_WARNING_THRESHOLD: usize = 3;
+/// How many of the worst fragmented identities of each kind to report
+pub const IDENTITY_REPORT_LIMIT: usize = 5;
+pub const SPLIT_LOG_EXTENSION: &str = "log";
+/// The file `--keep-empty-commits` modifies for a commit that didn't change anything, at the root of its repository
+pub const EMPTY_COMMIT_MARKER: &str = "(empty commit)";
+/// The file `--emit-repo-markers` adds at the root of each repository when its first commit appears
+pub const REPO_MARKER_FILE: &str = ".rource-repo";
+/// Points at the branch the remote considers its default, set by `git clone`
+pub const REMOTE_DEFAULT_BRANCH_REF: &str = "refs/remotes/origin/HEAD";
+pub const AVATAR_MANIFEST_FILENAME: &str = "missing-avatars.txt";
+/// Image types Gource will pick up from --user-image-dir
+pub const AVATAR_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg"];
+
+/// Paths excluded by `--sensible-defaults`, matched against the final (repo-relative) file path
+pub const SENSIBLE_DEFAULT_EXCLUDES: &[&str] = &[
+    "**/node_modules/**",
+    "**/vendor/**",
+    "**/target/**",
+    "**/dist/**",
+    "**/__pycache__/**",
+    "**/.venv/**",
+    "**/package-lock.json",
+    "**/yarn.lock",
+    "**/pnpm-lock.yaml",
+    "**/Cargo.lock",
+    "**/Gemfile.lock",
+    "**/poetry.lock",
+    "**/composer.lock",
+    "**/go.sum",
+];
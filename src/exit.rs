@@ -0,0 +1,49 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// Failures that get their own exit code, so scripts can tell "nothing to do" from "something broke".
+/// Attach one to an error with `.context(...)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitError {
+    NoRepositories,
+    AllRepositoriesSkipped,
+    InvalidArguments,
+}
+
+/// Anything else that fails the run
+pub const EXIT_FAILURE: u8 = 1;
+/// Reading or writing a file failed, e.g. the output or the temporary file
+pub const EXIT_DISK_ERROR: u8 = 4;
+
+impl ExitError {
+    pub const fn code(self) -> u8 {
+        match self {
+            Self::NoRepositories => 2,
+            Self::AllRepositoriesSkipped => 3,
+            Self::InvalidArguments => 5,
+        }
+    }
+}
+
+impl Display for ExitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NoRepositories => "No repositories found",
+            Self::AllRepositoriesSkipped => "Every repository was skipped",
+            Self::InvalidArguments => "Invalid arguments",
+        })
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+/// Pick the exit code for an error that ended the run
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    if let Some(exit) = error.downcast_ref::<ExitError>() {
+        return exit.code();
+    }
+    if error.chain().any(<dyn std::error::Error>::is::<io::Error>) {
+        return EXIT_DISK_ERROR;
+    }
+    EXIT_FAILURE
+}
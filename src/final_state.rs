@@ -0,0 +1,64 @@
+use crate::consts::EMPTY_COMMIT_MARKER;
+use crate::structs::{CommitInfo, GitLogConfig, GourceActionType, GourceLogFormat};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Remembers the last event for each file in a repository, so files that aren't in HEAD
+/// but were never shown as deleted can be deleted at the end of the log
+#[derive(Default)]
+pub struct FinalState {
+    files: Mutex<HashMap<String, (i64, GourceActionType)>>,
+}
+
+impl FinalState {
+    /// Record the changes from a single commit, commits may arrive in any order
+    pub fn record(&self, changes: &[GourceLogFormat]) {
+        let Ok(mut files) = self.files.lock() else {
+            return;
+        };
+        for change in changes {
+            // Same order as the sorted log, so on a tie this is the event Gource sees last
+            let event = (change.timestamp, change.r#type);
+            files
+                .entry(change.file.clone())
+                .and_modify(|last| *last = (*last).max(event))
+                .or_insert(event);
+        }
+    }
+
+    /// Delete every file that still exists at the end of the log but isn't in HEAD,
+    /// the deletes are attributed to the HEAD commit
+    pub fn reconcile<'a>(
+        self,
+        head: &CommitInfo,
+        head_files: impl Iterator<Item = &'a str>,
+        config: &GitLogConfig,
+    ) -> Vec<GourceLogFormat> {
+        // Directories count as existing too, the root commit may have been summarised
+        let mut existing = HashSet::new();
+        for path in head_files {
+            let file = head.log_path(path.to_string());
+            let mut directory = file.as_str();
+            while let Some((parent, _)) = directory.rsplit_once('/') {
+                if !existing.insert(parent.to_string()) {
+                    break;
+                }
+                directory = parent;
+            }
+            existing.insert(file);
+        }
+        // Not a real file, so it's never in HEAD
+        existing.insert(head.log_path(EMPTY_COMMIT_MARKER.to_string()));
+
+        let files = self.files.into_inner().unwrap_or_default();
+        let mut deletes = files
+            .into_iter()
+            .filter(|(file, (_, r#type))| {
+                *r#type != GourceActionType::D && !existing.contains(file)
+            })
+            .filter_map(|(file, _)| head.log_for_file(GourceActionType::D, file, config))
+            .collect::<Vec<_>>();
+        deletes.sort_unstable();
+        deletes
+    }
+}
@@ -0,0 +1,649 @@
+use crate::consts::{GIT_CLI_LOG_FORMAT, GIT_CLI_MAILMAP_LOG_FORMAT, REMOTE_DEFAULT_BRANCH_REF};
+use crate::final_state::FinalState;
+use crate::git_stuff::{finish_changes, set_changeset_size, set_sequence};
+use crate::progress::Progress;
+use crate::repo_marker::RepoMarker;
+use crate::source::RepositorySource;
+use crate::store::LogStore;
+use crate::structs::{
+    CommitInfo, DateSource, FilterReason, GitLogConfig, GourceActionType, GourceLogFormat,
+    MergeAttribution, RootCommitMode, StartPoint,
+};
+use crate::validation::lfs_filter;
+use crate::warnings::{record, Warning};
+use anyhow::{anyhow, bail, Context};
+use globset::GlobSet;
+use indicatif::ProgressBar;
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::io;
+use std::io::BufRead;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+/// Reads repositories by parsing the output of the system `git log`,
+/// this is slower than libgit2 but works anywhere git is installed
+pub struct GitCliSource;
+
+impl RepositorySource for GitCliSource {
+    fn read_log(
+        &self,
+        root_path: &Path,
+        path: &Path,
+        store: &LogStore,
+        progress_bar: &Progress,
+        config: &GitLogConfig,
+    ) -> anyhow::Result<()> {
+        let repo_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Failed to decode path for repo"))?
+            .to_str()
+            .unwrap_or("Non-UTF8 repo path")
+            .to_string();
+
+        let start = start_commit(path, config)?;
+        let hidden = hidden_commit(path, config)?;
+        let commit_count = if config.progress_count {
+            Some(
+                get_commit_count(path, &start, hidden.as_deref(), config)
+                    .context(format!("Failed to count commits for {repo_name}"))?
+                    .saturating_sub(config.skip_recent as u64)
+                    .div_ceil(config.sample_commits.get() as u64),
+            )
+        } else {
+            None
+        };
+        let sub_bar = progress_bar.add_counter(commit_count);
+        sub_bar.set_prefix(format!("Processing {repo_name}"));
+        sub_bar.set_message("Reading commit: ");
+
+        // Use NUL separated output so paths are never quoted or split,
+        // renames are only detected when the libgit2 diff would detect them too
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args([
+                "-c",
+                "core.quotePath=false",
+                "log",
+                "-z",
+                "--raw",
+                "--root",
+                "--diff-merges=first-parent",
+                "--no-abbrev",
+                "--no-color",
+                log_format(config),
+            ])
+            .arg(if config.detect_copies() {
+                "--find-copies"
+            } else if config.detect_renames() {
+                "--find-renames"
+            } else {
+                "--no-renames"
+            })
+            // --since and --until are checked per commit after --skip, git's own --since stops at the first
+            // commit older than it, which would drop newer commits after it when clocks are skewed
+            .arg(format!("--skip={}", config.skip_recent))
+            .arg(&start)
+            .args(&hidden)
+            .args(config.all_refs.then_some("--branches"))
+            .args(config.first_parent.then_some("--first-parent"))
+            .args(config.sequential.then_some("--reverse"))
+            // Parents always come before their children
+            .args(config.preserve_commit_order.then_some("--date-order"))
+            .stdout(Stdio::piped())
+            .spawn()
+            .context(format!("Failed to run git log for {repo_name}"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("Failed to read git log output for {repo_name}"))?;
+
+        let lfs_filter = if config.skip_lfs {
+            read_lfs_filter(path, &start)?.map(Arc::new)
+        } else {
+            None
+        };
+
+        let repo_marker = config.emit_repo_markers.then(RepoMarker::default);
+        let reader = CliLogReader {
+            root_path,
+            path,
+            excluded: resolve_excluded_commits(path, config)?,
+            store,
+            lfs_filter,
+            final_state: config
+                .emit_deletes_for_final_state
+                .then(FinalState::default),
+            repo_marker: repo_marker.as_ref(),
+            config,
+        };
+        let final_state = reader.read(io::BufReader::new(stdout), &sub_bar)?;
+
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("git log failed for {repo_name} with {status}");
+        }
+
+        if let Some(final_state) = final_state {
+            let mut deletes = reconcile_with_head(root_path, path, &start, final_state, config)?;
+            // After every commit
+            set_sequence(&mut deletes, usize::MAX, false, config);
+            store.store(deletes)?;
+        }
+        store.store(
+            repo_marker
+                .and_then(RepoMarker::finish)
+                .into_iter()
+                .collect(),
+        )?;
+
+        store.flush()?;
+        sub_bar.finish_with_message("Finished");
+        Ok(())
+    }
+}
+
+struct CliLogReader<'a> {
+    root_path: &'a Path,
+    path: &'a Path,
+    /// The full ids of the commits from --exclude-commit that are in this repository
+    excluded: HashSet<String>,
+    store: &'a LogStore,
+    lfs_filter: Option<Arc<GlobSet>>,
+    final_state: Option<FinalState>,
+    repo_marker: Option<&'a RepoMarker>,
+    config: &'a GitLogConfig,
+}
+
+impl CliLogReader<'_> {
+    /// Parse the NUL separated `git log -z --raw` output, one commit at a time,
+    /// returning the final state of the files once every commit has been read
+    fn read<R: BufRead>(
+        self,
+        reader: R,
+        sub_bar: &ProgressBar,
+    ) -> anyhow::Result<Option<FinalState>> {
+        let mut current: Option<(CliCommit, Vec<GourceLogFormat>)> = None;
+        let mut changeset_size = 0;
+        // With --sample-commits, the commits in between are parsed but not kept
+        let mut commit_index = 0_usize;
+        let mut sampled = true;
+        let mut tokens = reader.split(b'\0');
+
+        while let Some(token) = tokens.next() {
+            let token = token?;
+            let token = token.strip_prefix(b"\n").unwrap_or(&token);
+
+            if let Some(header) = token.strip_prefix(b"\x1e") {
+                // Start of a new commit, finish off the previous one
+                if let Some((commit, changes)) = current.take() {
+                    self.finish_commit(&commit, changes, changeset_size, commit_index)?;
+                }
+                changeset_size = 0;
+                sampled = commit_index.is_multiple_of(self.config.sample_commits.get());
+                commit_index += 1;
+                if !sampled {
+                    continue;
+                }
+                sub_bar.inc(1);
+                let mut commit = CliCommit::parse(self.root_path, self.path, header, self.config)?;
+                commit.info = commit.info.with_lfs_filter(self.lfs_filter.clone());
+                current = Some((commit, Vec::new()));
+            } else if let Some(raw) = token.strip_prefix(b":") {
+                if !sampled {
+                    // Still have to read past the paths
+                    let raw = String::from_utf8_lossy(raw);
+                    let status = raw.split(' ').next_back().unwrap_or_default();
+                    next_path(&mut tokens)?;
+                    if status.starts_with('R') || status.starts_with('C') {
+                        next_path(&mut tokens)?;
+                    }
+                    continue;
+                }
+                let Some((commit, changes)) = current.as_mut() else {
+                    bail!("Unexpected git log output, file change before commit");
+                };
+                changeset_size += 1;
+                // Renames and copies are followed by the old and new paths, everything else has one path
+                let raw = String::from_utf8_lossy(raw);
+                let status = raw.split(' ').next_back().unwrap_or_default();
+                let first_path = next_path(&mut tokens)?;
+                let path = if status.starts_with('R') || status.starts_with('C') {
+                    let new_path = next_path(&mut tokens)?;
+                    if status.starts_with('R') && self.config.rename_emit_delete_only {
+                        first_path
+                    } else {
+                        // A move is shown as the old path being deleted, then the new path added
+                        if let Some(old_path) = first_path.filter(|_| status.starts_with('R')) {
+                            changes.extend(commit.info.to_log(
+                                GourceActionType::D,
+                                &old_path,
+                                self.config,
+                            ));
+                        }
+                        new_path
+                    }
+                } else {
+                    first_path
+                };
+                let Some(path) = path else {
+                    record(
+                        Warning::PathUnreadable,
+                        format!("Unable to parse git log for {}", commit.info.id),
+                    );
+                    continue;
+                };
+                if let Some(log) = raw_change_to_log(&commit.info, &raw, &path, self.config) {
+                    changes.push(log);
+                }
+            } else {
+                // Blank separators between commits
+            }
+        }
+
+        if let Some((commit, changes)) = current.take() {
+            self.finish_commit(&commit, changes, changeset_size, commit_index)?;
+        }
+        Ok(self.final_state)
+    }
+
+    fn finish_commit(
+        &self,
+        commit: &CliCommit,
+        changes: Vec<GourceLogFormat>,
+        changeset_size: usize,
+        commit_index: usize,
+    ) -> anyhow::Result<()> {
+        if commit.info.is_root && self.config.root_commit_mode == RootCommitMode::Skip {
+            return Ok(());
+        }
+        if self.excluded.contains(&commit.info.id)
+            || !self.config.in_date_range(commit.time)
+            || !self.config.reads_commit(commit.is_merge)
+            || !self.config.author_filter.allows(&commit.author)
+        {
+            return Ok(());
+        }
+
+        let (mut changes, changeset_size) = if changeset_size == 0 && self.config.keep_empty_commits
+        {
+            (
+                commit
+                    .info
+                    .empty_commit_marker(self.config)
+                    .into_iter()
+                    .collect(),
+                0,
+            )
+        } else {
+            (changes, changeset_size)
+        };
+        set_changeset_size(&mut changes, changeset_size, self.config);
+        // The log is newest first unless it's reversed
+        set_sequence(
+            &mut changes,
+            commit_index,
+            !self.config.sequential,
+            self.config,
+        );
+
+        let changes = finish_changes(&commit.info, changes, self.config);
+        if let Some(final_state) = &self.final_state {
+            final_state.record(&changes);
+        }
+        if let Some(repo_marker) = self.repo_marker {
+            repo_marker.record(&commit.info, &changes);
+        }
+        self.store.store(changes)
+    }
+}
+
+/// Delete the files that are left over once every commit has been read but aren't in HEAD,
+/// or whichever commit the history was read from
+fn reconcile_with_head(
+    root_path: &Path,
+    path: &Path,
+    start: &str,
+    final_state: FinalState,
+    config: &GitLogConfig,
+) -> anyhow::Result<Vec<GourceLogFormat>> {
+    let header = git_output(path, &["log", "-1", log_format(config), start])?;
+    let header = header
+        .trim_end()
+        .strip_prefix('\x1e')
+        .ok_or_else(|| anyhow!("Unexpected git log output for HEAD in {}", path.display()))?;
+    let head = CliCommit::parse(root_path, path, header.as_bytes(), config)?;
+
+    let files = git_output(path, &["ls-tree", "-r", "-z", "--name-only", start])?;
+    let files = files.split('\0').filter(|p| !p.is_empty());
+    Ok(final_state.reconcile(&head.info, files, config))
+}
+
+struct CliCommit {
+    info: CommitInfo,
+    /// Its --date-source date, before any time offset
+    time: i64,
+    /// The name of its author after the mailmap, for --author and --exclude-author
+    author: String,
+    is_merge: bool,
+}
+
+impl CliCommit {
+    /// Parse the commit header written by `log_format`
+    fn parse(
+        root_path: &Path,
+        path: &Path,
+        header: &[u8],
+        config: &GitLogConfig,
+    ) -> anyhow::Result<Self> {
+        let header = String::from_utf8_lossy(header);
+        let mut fields = header.split('\x1f');
+        let (
+            Some(id),
+            Some(timestamp),
+            Some(author_name),
+            Some(parents),
+            Some(author_email),
+            Some(committer_name),
+            Some(committer_email),
+            Some(author_timestamp),
+            Some(co_authors),
+        ) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        )
+        else {
+            bail!("Unable to parse git log header {header:?}");
+        };
+        let time = match config.date_source {
+            DateSource::Author => author_timestamp.parse()?,
+            DateSource::Committer => timestamp.parse()?,
+        };
+        let parent_count = parents.split_whitespace().count();
+        let merged = match parents.split_whitespace().nth(1) {
+            Some(parent) if config.merge_attribution == MergeAttribution::FirstParentAuthor => {
+                Some(git_output(
+                    path,
+                    &[
+                        "log",
+                        "-1",
+                        if config.use_mailmap {
+                            "--format=%aN%x1f%aE"
+                        } else {
+                            "--format=%an%x1f%ae"
+                        },
+                        parent,
+                    ],
+                )?)
+            }
+            _ => None,
+        };
+        let author = merged
+            .as_deref()
+            .and_then(|merged| merged.trim_end().split_once('\x1f'))
+            .unwrap_or((author_name, author_email));
+        let ((name, email), committer) = config
+            .identity
+            .pick(author, (committer_name, committer_email));
+
+        let info = CommitInfo::new(
+            root_path,
+            path,
+            id.to_string(),
+            time,
+            &CommitInfo::username(name, Some(email), config),
+            parent_count == 0,
+            config,
+        )?;
+        let info = match committer {
+            Some((name, email)) => info.with_committer(
+                path,
+                &CommitInfo::username(name, Some(email), config),
+                config,
+            ),
+            None => info,
+        };
+        Ok(Self {
+            info: if config.shows_co_authors() {
+                info.with_co_authors(path, co_authors.split('\x1d'), config)
+            } else {
+                info
+            },
+            time,
+            author: author_name.to_string(),
+            is_merge: parent_count > 1,
+        })
+    }
+}
+
+/// Read the next NUL separated path from the log, `None` if it isn't valid UTF-8
+fn next_path<I>(tokens: &mut I) -> anyhow::Result<Option<String>>
+where
+    I: Iterator<Item = io::Result<Vec<u8>>>,
+{
+    let token = tokens
+        .next()
+        .ok_or_else(|| anyhow!("Unexpected end of git log output"))??;
+    Ok(String::from_utf8(token).ok())
+}
+
+/// Convert a `--raw` change line (without the leading ':') into a log record,
+/// e.g. `100644 100755 <old id> <new id> M`
+fn raw_change_to_log(
+    info: &CommitInfo,
+    raw: &str,
+    path: &str,
+    config: &GitLogConfig,
+) -> Option<GourceLogFormat> {
+    let fields = raw.split(' ').collect::<Vec<_>>();
+    let [old_mode, new_mode, old_id, new_id, status] = fields[..] else {
+        record(
+            Warning::PathUnreadable,
+            format!("Unable to parse git log change {raw:?} for {}", info.id),
+        );
+        return None;
+    };
+
+    let r#type = match status.chars().next() {
+        // The new path is left to appear when it's next changed
+        Some('R') if config.rename_emit_delete_only => GourceActionType::D,
+        // For a rename the old path's delete has already been added
+        Some('A' | 'R') => GourceActionType::A,
+        Some('C') if config.copy_as_added => GourceActionType::A,
+        Some('D') => GourceActionType::D,
+        Some('M' | 'C' | 'T') => GourceActionType::M,
+        // Unmerged or unknown changes don't change the tree
+        _ => return None,
+    };
+
+    // A chmod shows up as a modification, but the content hasn't changed
+    if config.skip_mode_only_changes && status == "M" && old_id == new_id && old_mode != new_mode {
+        info.explain(path, FilterReason::ModeOnlyChange, config);
+        return None;
+    }
+
+    info.to_log(r#type, path, config)
+}
+
+/// Look up the --exclude-commit ids in a repository with `git rev-parse`, ids it doesn't have are left out
+fn resolve_excluded_commits(path: &Path, config: &GitLogConfig) -> anyhow::Result<HashSet<String>> {
+    let mut excluded = HashSet::with_capacity(config.excluded_commits.len());
+    for id in &config.excluded_commits {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args(["rev-parse", "--verify", &format!("{id}^{{commit}}")])
+            .output()
+            .context("Failed to run git, is it installed and on your PATH?")?;
+        if output.status.success() {
+            excluded.insert(String::from_utf8_lossy(&output.stdout).trim().to_string());
+            continue;
+        }
+        if String::from_utf8_lossy(&output.stderr).contains("ambiguous") {
+            warn!(
+                "--exclude-commit {id} matches more than one commit in {}, ignoring it",
+                path.display()
+            );
+        }
+    }
+    Ok(excluded)
+}
+
+/// Read the LFS patterns from the .gitattributes file in HEAD
+fn read_lfs_filter(path: &Path, start: &str) -> anyhow::Result<Option<GlobSet>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .arg("show")
+        .arg(format!("{start}:.gitattributes"))
+        .stderr(Stdio::null())
+        .output()
+        .context("Failed to run git, is it installed and on your PATH?")?;
+    if !output.status.success() {
+        // There's no .gitattributes
+        return Ok(None);
+    }
+    lfs_filter(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The revision a repository's history is read back from, HEAD unless --branch or --default-branch picks another
+fn start_commit(path: &Path, config: &GitLogConfig) -> anyhow::Result<String> {
+    match &config.start_point {
+        StartPoint::Head => {}
+        StartPoint::DefaultBranch => {
+            if let Some(id) = default_branch(path) {
+                return Ok(id);
+            }
+            debug!(
+                "No default branch found for {}, reading from HEAD",
+                path.display()
+            );
+        }
+        StartPoint::Branch(name) => {
+            return resolve_commit(path, &format!("refs/heads/{name}"))
+                .ok_or_else(|| anyhow!("{} has no local branch {name}", path.display()));
+        }
+        StartPoint::Commit(sha) => {
+            return resolve_commit(path, sha)
+                .ok_or_else(|| anyhow!("{} has no commit {sha}", path.display()));
+        }
+    }
+    Ok("HEAD".to_string())
+}
+
+/// `^<SHA>` for the --since-commit, which leaves it and its ancestors out of the history
+fn hidden_commit(path: &Path, config: &GitLogConfig) -> anyhow::Result<Option<String>> {
+    config
+        .since_commit
+        .as_ref()
+        .map(|sha| {
+            resolve_commit(path, sha)
+                .map(|id| format!("^{id}"))
+                .ok_or_else(|| anyhow!("{} has no commit {sha}", path.display()))
+        })
+        .transpose()
+}
+
+/// The tip of the branch origin's HEAD points at, or of the branch named by init.defaultBranch
+fn default_branch(path: &Path) -> Option<String> {
+    let mut candidates = Vec::new();
+    if let Ok(target) = git_output(path, &["symbolic-ref", "-q", REMOTE_DEFAULT_BRANCH_REF]) {
+        let target = target.trim().to_string();
+        // The local branch may have commits that haven't been pushed yet
+        if let Some(name) = target.strip_prefix("refs/remotes/origin/") {
+            candidates.push(format!("refs/heads/{name}"));
+        }
+        candidates.push(target);
+    }
+    if let Ok(name) = git_output(path, &["config", "init.defaultBranch"]) {
+        candidates.push(format!("refs/heads/{}", name.trim()));
+    }
+    candidates
+        .iter()
+        .find_map(|name| resolve_commit(path, name))
+}
+
+/// The full id of the commit a ref points at, if there is one
+fn resolve_commit(path: &Path, name: &str) -> Option<String> {
+    git_output(
+        path,
+        &["rev-parse", "--verify", "-q", &format!("{name}^{{commit}}")],
+    )
+    .ok()
+    .map(|id| id.trim().to_string())
+}
+
+/// The --format for commit headers, with or without the mailmap applied
+const fn log_format(config: &GitLogConfig) -> &'static str {
+    if config.use_mailmap {
+        GIT_CLI_MAILMAP_LOG_FORMAT
+    } else {
+        GIT_CLI_LOG_FORMAT
+    }
+}
+
+/// How many commits the history has, the same ones `git log` is given
+fn get_commit_count(
+    path: &Path,
+    start: &str,
+    hidden: Option<&str>,
+    config: &GitLogConfig,
+) -> anyhow::Result<u64> {
+    let mut args = vec!["rev-list", "--count", start];
+    args.extend(hidden);
+    if config.all_refs {
+        args.push("--branches");
+    }
+    if config.first_parent {
+        args.push("--first-parent");
+    }
+    Ok(git_output(path, &args)?.trim().parse()?)
+}
+
+/// Run a git command in the repository and return its output
+fn git_output(path: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .output()
+        .context("Failed to run git, is it installed and on your PATH?")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed for {}: {}",
+            args.join(" "),
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_commit_count, start_commit};
+    use crate::test_support::{config, two_branches};
+
+    #[test]
+    fn all_refs_counts_commits_on_every_branch() -> anyhow::Result<()> {
+        let fixture = two_branches()?;
+        for (args, count) in [(&[][..], 2), (&["--all-refs"][..], 3)] {
+            let config = config(&fixture.path(), args)?;
+            let start = start_commit(&fixture.path(), &config)?;
+            assert_eq!(
+                get_commit_count(&fixture.path(), &start, None, &config)?,
+                count
+            );
+        }
+        Ok(())
+    }
+}
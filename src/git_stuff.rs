@@ -1,28 +1,159 @@
-use crate::serde::{batch_log_write, serialize_logs};
-use crate::structs::GourceLogFormat;
+use crate::final_state::FinalState;
+use crate::repo_marker::RepoMarker;
+use crate::source::RepositorySource;
+use crate::store::LogStore;
+use crate::structs::{
+    mailmapped, CommitInfo, DateSource, GitLogConfig, GourceActionType, GourceLogFormat,
+    RootCommitMode, StartPoint,
+};
+use crate::validation::lfs_filter;
+use crate::warnings::{record, Warning};
 use anyhow::Context;
-use git2::{Commit, Oid, Repository};
-use log::error;
+use git2::{BranchType, Commit, DiffFindOptions, Mailmap, Oid, Repository};
+use globset::GlobSet;
+use indicatif::ProgressBar;
+use log::{debug, error, warn};
 
-use crate::consts::{DEFAULT_PROGRESS_STYLE, DEFAULT_SPINNER_STYLE, DEFAULT_SPINNER_TICK_STYLE};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use crate::consts::{CORRUPT_REPOSITORY_FAILURES, REMOTE_DEFAULT_BRANCH_REF};
+use crate::progress::Progress;
 use rayon::prelude::*;
-use std::io::Write;
-use std::path::PathBuf;
-use std::sync::Mutex;
-use std::time::Duration;
-use std::{fs, io};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Read the git logs for all the repositories
+pub fn read_repositories(
+    root_path: &Path,
+    repositories: &[PathBuf],
+    store: &LogStore,
+    progress_bar: &Progress,
+    config: &GitLogConfig,
+) -> anyhow::Result<()> {
+    let source = config.backend.source();
+    let read_one = |path: &PathBuf| {
+        if let Some(reference) = &config.snapshot_ref {
+            return read_snapshot(root_path, path, reference, store, progress_bar, config);
+        }
+        source.read_log(root_path, path, store, progress_bar, config)
+    };
+    // With --keep-going a repository that fails is left out instead of ending the run
+    let read = |path: &PathBuf| {
+        read_one(path).or_else(|e| {
+            if !config.keep_going {
+                return Err(e);
+            }
+            record(
+                Warning::RepositoryFailed,
+                format!("Failed to read {}, skipping it: {e:#}", path.display()),
+            );
+            Ok(())
+        })
+    };
+
+    if config.sequential {
+        return repositories.iter().try_for_each(read);
+    }
+    let Some(concurrency) = config.repo_concurrency else {
+        return repositories.par_iter().map(read).collect();
+    };
+
+    // Use a fixed number of plain threads to work through the repositories,
+    // each repository's commits are still processed on the shared rayon pool
+    let queue = Mutex::new(repositories.iter());
+    let results = Mutex::new(Vec::with_capacity(repositories.len()));
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.get().min(repositories.len()) {
+            scope.spawn(|| {
+                while let Some(path) = queue.lock().ok().and_then(|mut q| q.next()) {
+                    let result = read(path);
+                    let Ok(mut results) = results.lock() else {
+                        error!("Failed to lock results for repository: {:?}", path);
+                        break;
+                    };
+                    results.push(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .map_err(|e| anyhow::anyhow!("Failed to collect repository results - {:?}", e))?
+        .into_iter()
+        .collect()
+}
+
+/// Collect the distinct authors of every commit, without diffing anything.
+/// These are the names aliases match, or `Name <email>` with `use_email`
+pub fn read_authors(
+    repositories: &[PathBuf],
+    use_email: bool,
+    config: &GitLogConfig,
+) -> anyhow::Result<BTreeSet<String>> {
+    let authors = repositories
+        .par_iter()
+        .map(|path| {
+            let repo = Repository::open(path)?;
+            let start =
+                start_commit(&repo, config).context(format!("Processing {}", path.display()))?;
+            let mut revwalk = repo.revwalk()?;
+            push_start(&mut revwalk, &repo, start.id(), config)?;
+            let mailmap = load_mailmap(&repo, config);
+
+            let mut authors = BTreeSet::new();
+            for revision in revwalk {
+                let commit = repo.find_commit(revision?)?;
+                let (author, committer) = config.identity.pick(
+                    mailmapped(commit.author(), mailmap.as_ref()),
+                    mailmapped(commit.committer(), mailmap.as_ref()),
+                );
+                for signature in std::iter::once(author).chain(committer) {
+                    let Some(name) = signature.name() else {
+                        record(
+                            Warning::CommitUnreadable,
+                            format!("Unable to read the author of {}", commit.id()),
+                        );
+                        continue;
+                    };
+                    let username = CommitInfo::username(name, signature.email(), config);
+                    authors.insert(if use_email {
+                        format!("{username} <{}>", signature.email().unwrap_or_default())
+                    } else {
+                        username
+                    });
+                }
+            }
+            Ok(authors)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(authors.into_iter().flatten().collect())
+}
+
+/// Reads repositories using libgit2
+pub struct LibGit2Source;
+
+impl RepositorySource for LibGit2Source {
+    fn read_log(
+        &self,
+        root_path: &Path,
+        path: &Path,
+        store: &LogStore,
+        progress_bar: &Progress,
+        config: &GitLogConfig,
+    ) -> anyhow::Result<()> {
+        read_git_log(root_path, path, store, progress_bar, config)
+    }
+}
 
 /// Read the git log for a repository and parse into our struct
 pub fn read_git_log(
-    root_path: &PathBuf,
-    path: &PathBuf,
-    locked_output_writer: Option<&Mutex<io::BufWriter<fs::File>>>,
-    progress_bar: &MultiProgress,
-    max_changeset_size: Option<usize>,
-) -> anyhow::Result<Vec<GourceLogFormat>> {
-    let logs: Vec<GourceLogFormat> = Vec::new();
-
+    root_path: &Path,
+    path: &Path,
+    store: &LogStore,
+    progress_bar: &Progress,
+    config: &GitLogConfig,
+) -> anyhow::Result<()> {
     let repo_name = path
         .file_name()
         .ok_or_else(|| anyhow::anyhow!("Failed to decode path for repo"))?
@@ -31,136 +162,892 @@ pub fn read_git_log(
         .to_string();
 
     // Create a temporary progress bar while we open the repository
-    let sub_bar = progress_bar.add(ProgressBar::new_spinner().with_style(
-        ProgressStyle::with_template(DEFAULT_SPINNER_STYLE)?.tick_chars(DEFAULT_SPINNER_TICK_STYLE),
-    ));
+    let sub_bar = progress_bar.add_spinner();
     sub_bar.set_prefix(format!("Initialising Repository {repo_name}"));
-    sub_bar.enable_steady_tick(Duration::from_millis(100));
 
     // Open the repository
     let repo = Repository::open(path)?;
+    let start = start_commit(&repo, config)
+        .context(format!("Processing {repo_name}"))?
+        .id();
 
     // Reset the progress bar
     progress_bar.remove(&sub_bar);
 
-    // Create a new progress bar for processing commits
-    let commit_count = get_commit_count(&repo)?;
-    let sub_bar = progress_bar.add(
-        ProgressBar::new(commit_count as u64)
-            .with_style(ProgressStyle::with_template(DEFAULT_PROGRESS_STYLE)?),
+    // Create a new progress bar for processing commits, counting them needs an extra walk.
+    // The sequential walk is oldest first, so it needs the count to skip the newest commits
+    let commit_count = if config.progress_count || (config.sequential && config.skip_recent > 0) {
+        Some(get_commit_count(&repo, start, config)?.saturating_sub(config.skip_recent))
+    } else {
+        None
+    };
+    let sub_bar = progress_bar.add_counter(
+        commit_count
+            .filter(|_| config.progress_count)
+            .map(|count| count.div_ceil(config.sample_commits.get()) as u64),
     );
 
     sub_bar.set_prefix(format!("Processing {repo_name}"));
     sub_bar.set_message("Reading commit: ");
 
     let mut revwalk = repo.revwalk()?;
-    revwalk
-        .push_head()
-        .context(format!("Processing {repo_name}"))?;
-    revwalk.set_sorting(git2::Sort::TIME)?;
+    push_start(&mut revwalk, &repo, start, config)?;
+    // Topological order makes sure parents always come before their children
+    let sorting = if config.preserve_commit_order {
+        git2::Sort::TOPOLOGICAL | git2::Sort::TIME
+    } else {
+        git2::Sort::TIME
+    };
+    revwalk.set_sorting(sorting)?;
+
+    let lfs_filter = if config.skip_lfs {
+        read_lfs_filter(&repo, start)?.map(Arc::new)
+    } else {
+        None
+    };
 
-    let log_lock = Mutex::new(logs);
-    let commits = revwalk.collect::<Vec<Result<Oid, _>>>();
+    let final_state = config
+        .emit_deletes_for_final_state
+        .then(FinalState::default);
+    let repo_marker = config.emit_repo_markers.then(RepoMarker::default);
+    let commits = CommitReader {
+        root_path,
+        excluded: resolve_excluded_commits(&repo, config),
+        lfs_filter: lfs_filter.as_ref(),
+        final_state: final_state.as_ref(),
+        repo_marker: repo_marker.as_ref(),
+        failures: ConsecutiveFailures::default(),
+        store,
+        config,
+    };
 
-    commits.par_iter().for_each(|revision| {
-        let Ok(repo) = Repository::open(path) else {
-            error!("Failed to open repository: {:?}", path);
-            return;
-        };
+    if config.sequential {
+        // Stream the commits oldest first on this thread, the newest commits come last
+        // so only take as many as we counted to skip the most recent ones
+        revwalk.set_sorting(sorting | git2::Sort::REVERSE)?;
+        let mailmap = load_mailmap(&repo, config);
+        for (index, revision) in revwalk
+            .take(commit_count.unwrap_or(usize::MAX))
+            .step_by(config.sample_commits.get())
+            .enumerate()
+        {
+            sub_bar.inc(1);
+            commits.process(&repo, mailmap.as_ref(), &revision, index);
+        }
+    } else {
+        // The walk is newest first, so skipping drops the most recent commits
+        // and everything after the first commit older than --since is older too
+        let revisions = revwalk
+            .skip(config.skip_recent)
+            .take_while(|revision| !is_before_since(&repo, revision, config))
+            .step_by(config.sample_commits.get())
+            .collect::<Vec<Result<Oid, _>>>();
+
+        commits.process_parallel(path, &revisions, &sub_bar);
+    }
+
+    commits.check_corruption(&repo_name)?;
+
+    if let Some(final_state) = final_state {
+        let mut deletes = reconcile_with_head(root_path, &repo, start, final_state, config)?;
+        // After every commit
+        set_sequence(&mut deletes, usize::MAX, false, config);
+        store.store(deletes)?;
+    }
+    store.store(
+        repo_marker
+            .and_then(RepoMarker::finish)
+            .into_iter()
+            .collect(),
+    )?;
 
-        sub_bar.inc(1);
+    store.flush()?;
+    sub_bar.finish_with_message("Finished");
+    Ok(())
+}
 
+/// Delete the files that are left over once every commit has been read but aren't in HEAD,
+/// or whichever commit the history was read from
+fn reconcile_with_head(
+    root_path: &Path,
+    repo: &Repository,
+    start: Oid,
+    final_state: FinalState,
+    config: &GitLogConfig,
+) -> anyhow::Result<Vec<GourceLogFormat>> {
+    let head = repo.find_commit(start)?;
+    let info = CommitInfo::from_commit(
+        root_path,
+        repo,
+        &head,
+        load_mailmap(repo, config).as_ref(),
+        config,
+    )?;
+    let files = tree_files(&head.tree()?)?;
+    Ok(final_state.reconcile(&info, files.iter().map(String::as_str), config))
+}
+
+/// Every file in a tree and its subtrees, as paths from the root of the repository
+fn tree_files(tree: &git2::Tree<'_>) -> anyhow::Result<Vec<String>> {
+    let mut files = Vec::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |directory, entry| {
+        if entry.kind() != Some(git2::ObjectType::Tree) {
+            if let Some(name) = entry.name() {
+                files.push(format!("{directory}{name}"));
+            }
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+    Ok(files)
+}
+
+/// Add every file in a ref's tree at the time of its commit, for --snapshot-ref.
+/// Repositories without the ref are skipped
+fn read_snapshot(
+    root_path: &Path,
+    path: &Path,
+    reference: &str,
+    store: &LogStore,
+    progress_bar: &Progress,
+    config: &GitLogConfig,
+) -> anyhow::Result<()> {
+    let sub_bar = progress_bar.add_spinner();
+    sub_bar.set_prefix(format!("Snapshotting {}", path.display()));
+
+    let repo = Repository::open(path)?;
+    let Ok(commit) = repo
+        .revparse_single(reference)
+        .and_then(|object| object.peel_to_commit())
+    else {
+        record(
+            Warning::RepositorySkipped,
+            format!("{} has no commit at {reference}", path.display()),
+        );
+        progress_bar.remove(&sub_bar);
+        return Ok(());
+    };
+
+    let lfs_filter = if config.skip_lfs {
+        read_lfs_filter(&repo, commit.id())?.map(Arc::new)
+    } else {
+        None
+    };
+    let info = CommitInfo::from_commit(
+        root_path,
+        &repo,
+        &commit,
+        load_mailmap(&repo, config).as_ref(),
+        config,
+    )?
+    .with_lfs_filter(lfs_filter);
+    let changes = tree_files(&commit.tree()?)?
+        .into_iter()
+        .filter_map(|file| info.to_log(GourceActionType::A, &file, config))
+        .collect::<Vec<_>>();
+    if config.emit_repo_markers {
+        let repo_marker = RepoMarker::default();
+        repo_marker.record(&info, &changes);
+        store.store(repo_marker.finish().into_iter().collect())?;
+    }
+    store.store(changes)?;
+    store.flush()?;
+    sub_bar.finish_with_message("Finished");
+    Ok(())
+}
+
+/// Look up the --exclude-commit ids in a repository, abbreviated ones are resolved through
+/// its object database and ids it doesn't have are left out
+fn resolve_excluded_commits(repo: &Repository, config: &GitLogConfig) -> HashSet<Oid> {
+    config
+        .excluded_commits
+        .iter()
+        .filter_map(|id| {
+            match repo
+                .revparse_single(&format!("{id}^{{commit}}"))
+                .and_then(|object| object.peel_to_commit())
+            {
+                Ok(commit) => Some(commit.id()),
+                Err(e) => {
+                    if e.code() == git2::ErrorCode::Ambiguous {
+                        warn!(
+                            "--exclude-commit {id} matches more than one commit in {}, ignoring it",
+                            repo.path().display()
+                        );
+                    }
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Turns each commit in a repository into log records and stores them
+struct CommitReader<'a> {
+    root_path: &'a Path,
+    /// The commits from --exclude-commit that are in this repository
+    excluded: HashSet<Oid>,
+    lfs_filter: Option<&'a Arc<GlobSet>>,
+    final_state: Option<&'a FinalState>,
+    repo_marker: Option<&'a RepoMarker>,
+    failures: ConsecutiveFailures,
+    store: &'a LogStore,
+    config: &'a GitLogConfig,
+}
+
+impl CommitReader<'_> {
+    /// Warn, or fail with --strict, when so many commits in a row failed that the repository looks corrupt
+    fn check_corruption(&self, repo_name: &str) -> anyhow::Result<()> {
+        let failures = self.failures.worst();
+        if failures >= CORRUPT_REPOSITORY_FAILURES {
+            let message = format!(
+                "{failures} commits in a row could not be read from {repo_name}, \
+                its history is likely corrupt and its log is unreliable"
+            );
+            if self.config.strict {
+                anyhow::bail!(message);
+            }
+            warn!("{message}");
+        }
+        Ok(())
+    }
+
+    /// Process the commits on rayon's threads. Neither the repository nor its mailmap
+    /// can be shared between threads, so each of rayon's jobs opens its own
+    fn process_parallel(
+        &self,
+        path: &Path,
+        revisions: &[Result<Oid, git2::Error>],
+        sub_bar: &ProgressBar,
+    ) {
+        revisions.par_iter().enumerate().for_each_init(
+            || {
+                Repository::open(path).ok().map(|repo| {
+                    let mailmap = load_mailmap(&repo, self.config);
+                    (repo, mailmap)
+                })
+            },
+            |opened, (index, revision)| {
+                let Some((repo, mailmap)) = opened else {
+                    record(
+                        Warning::CommitUnreadable,
+                        format!("Failed to open repository: {}", path.display()),
+                    );
+                    return;
+                };
+                sub_bar.inc(1);
+                self.process(repo, mailmap.as_ref(), revision, index);
+            },
+        );
+    }
+
+    /// The index is the commit's position in the walk, to order commits from the same second
+    fn process(
+        &self,
+        repo: &Repository,
+        mailmap: Option<&Mailmap>,
+        revision: &Result<Oid, git2::Error>,
+        index: usize,
+    ) {
         let Ok(revision) = revision else {
-            error!("Failed to read revision: {:?}", revision);
+            self.failures.failed();
+            record(
+                Warning::CommitUnreadable,
+                format!("Failed to read revision: {revision:?}"),
+            );
             return;
         };
 
+        if self.excluded.contains(revision) {
+            return;
+        }
+
         let Ok(commit) = &repo.find_commit(*revision) else {
-            error!("Failed to find commit: {:?}", revision);
+            self.failures.failed();
+            record(
+                Warning::CommitUnreadable,
+                format!("Failed to find commit: {revision:?}"),
+            );
             return;
         };
+        if !self
+            .config
+            .in_date_range(self.config.date_source.time(commit))
+            || !self.config.reads_commit(commit.parent_count() > 1)
+        {
+            return;
+        }
+        // Before the diff, which is most of the work
+        let author = mailmapped(commit.author(), mailmap);
+        if !self
+            .config
+            .author_filter
+            .allows(author.name().unwrap_or_default())
+        {
+            return;
+        }
 
-        let Ok(mut changes) = compute_diff(root_path, &repo, commit, max_changeset_size) else {
-            error!("Failed to compute diff for commit: {:?}", revision);
+        let Ok(mut changes) = compute_diff(
+            self.root_path,
+            repo,
+            commit,
+            mailmap,
+            self.lfs_filter,
+            self.repo_marker,
+            self.config,
+        ) else {
+            self.failures.failed();
+            record(
+                Warning::DiffFailed,
+                format!("Failed to compute diff for commit: {revision:?}"),
+            );
             return;
         };
+        self.failures.succeeded();
+        set_sequence(&mut changes, index, !self.config.sequential, self.config);
 
-        if changes.is_empty() {
-            return;
+        if let Some(final_state) = self.final_state {
+            final_state.record(&changes);
         }
-
-        if let Some(writer) = locked_output_writer.as_ref() {
-            let Ok(changes) = serialize_logs(&changes[..]) else {
-                error!("Failed to serialize logs for commit: {:?}", revision);
-                return;
-            };
-            let Ok(mut writer) = writer.lock() else {
-                error!("Failed to lock writer for commit: {:?}", revision);
-                return;
-            };
-            if let Err(e) = batch_log_write(&mut writer, changes) {
-                error!("Failed to write logs for commit: {:?} - {:?}", revision, e);
-            }
-        } else {
-            let Ok(mut logs) = log_lock.lock() else {
-                error!("Failed to lock writer for commit: {:?}", revision);
-                return;
-            };
-            logs.append(&mut changes);
+        if let Err(e) = self.store.store(changes) {
+            record(
+                Warning::StoreFailed,
+                format!("Failed to store logs for commit: {revision:?} - {e:?}"),
+            );
         }
-    });
+    }
+}
+
+/// Tells one bad commit apart from a broken repository. When commits are read in parallel
+/// "in a row" is in the order they finish, which is close enough
+#[derive(Default)]
+struct ConsecutiveFailures {
+    current: AtomicUsize,
+    worst: AtomicUsize,
+}
 
-    if let Some(writer) = locked_output_writer {
-        let mut writer = writer
-            .lock()
-            .map_err(|e| anyhow::anyhow!("Failed to lock writer for buffer flush - {:?}", e))?;
-        writer.flush()?;
+impl ConsecutiveFailures {
+    fn failed(&self) {
+        let current = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        self.worst.fetch_max(current, Ordering::Relaxed);
     }
 
-    sub_bar.finish_with_message("Finished");
+    fn succeeded(&self) {
+        self.current.store(0, Ordering::Relaxed);
+    }
 
-    Ok(log_lock.into_inner()?)
+    fn worst(&self) -> usize {
+        self.worst.load(Ordering::Relaxed)
+    }
 }
 
-fn get_commit_count(repo: &Repository) -> anyhow::Result<usize> {
-    let mut revwalk = repo.revwalk()?;
-    revwalk
-        .push_head()
-        .context(format!("Processing {:?}", repo.path()))?;
+/// The repository's mailmap, unless it's turned off with --no-mailmap
+fn load_mailmap(repo: &Repository, config: &GitLogConfig) -> Option<Mailmap> {
+    if !config.use_mailmap {
+        return None;
+    }
+    repo.mailmap()
+        .map_err(|e| {
+            warn!(
+                "Unable to read the mailmap of {}, using names as committed: {e}",
+                repository_root(repo).display()
+            );
+        })
+        .ok()
+}
 
+/// Was the commit made before --since? Unreadable commits aren't, so they're still reported.
+/// Author dates don't follow the walk's order, so they're checked per commit instead
+fn is_before_since(
+    repo: &Repository,
+    revision: &Result<Oid, git2::Error>,
+    config: &GitLogConfig,
+) -> bool {
+    if config.date_source == DateSource::Author {
+        return false;
+    }
+    config.since.is_some_and(|since| {
+        revision
+            .as_ref()
+            .ok()
+            .and_then(|oid| repo.find_commit(*oid).ok())
+            .is_some_and(|commit| commit.time().seconds() < since)
+    })
+}
+
+/// Read the LFS patterns from the .gitattributes file in the commit the history is read from
+fn read_lfs_filter(repo: &Repository, start: Oid) -> anyhow::Result<Option<GlobSet>> {
+    let tree = repo.find_commit(start)?.tree()?;
+    let Ok(entry) = tree.get_path(Path::new(".gitattributes")) else {
+        return Ok(None);
+    };
+    let blob = entry.to_object(repo)?.peel_to_blob()?;
+    lfs_filter(&String::from_utf8_lossy(blob.content()))
+}
+
+fn get_commit_count(repo: &Repository, start: Oid, config: &GitLogConfig) -> anyhow::Result<usize> {
+    let mut revwalk = repo.revwalk()?;
+    push_start(&mut revwalk, repo, start, config)?;
     Ok(revwalk.count())
 }
 
+/// Walk back from the start commit, and with --all-refs from every local branch too.
+/// Commits reachable from more than one of them are only visited once, with --first-parent merged branches aren't visited
+fn push_start(
+    revwalk: &mut git2::Revwalk<'_>,
+    repo: &Repository,
+    start: Oid,
+    config: &GitLogConfig,
+) -> anyhow::Result<()> {
+    revwalk.push(start)?;
+    if let Some(sha) = &config.since_commit {
+        revwalk.hide(find_commit_sha(repo, sha)?.id())?;
+    }
+    if config.first_parent {
+        revwalk.simplify_first_parent()?;
+    }
+    if config.all_refs {
+        for branch in repo.branches(Some(BranchType::Local))? {
+            if let Some(tip) = branch?.0.get().target() {
+                revwalk.push(tip)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The commit a repository's history is read back from, HEAD unless --branch, --default-branch or --until-commit picks another
+pub fn start_commit<'r>(repo: &'r Repository, config: &GitLogConfig) -> anyhow::Result<Commit<'r>> {
+    match &config.start_point {
+        StartPoint::Head => {}
+        StartPoint::DefaultBranch => {
+            if let Some(commit) = default_branch(repo) {
+                return Ok(commit);
+            }
+            debug!(
+                "No default branch found for {}, reading from HEAD",
+                repo.path().display()
+            );
+        }
+        StartPoint::Branch(name) => {
+            let branch = repo.find_branch(name, BranchType::Local).map_err(|_| {
+                anyhow::anyhow!(
+                    "{} has no local branch {name}",
+                    repository_root(repo).display()
+                )
+            })?;
+            return Ok(branch.get().peel_to_commit()?);
+        }
+        StartPoint::Commit(sha) => return find_commit_sha(repo, sha),
+    }
+    Ok(repo.head()?.peel_to_commit()?)
+}
+
+/// The commit a full or abbreviated SHA names, the error says which repository doesn't have it
+fn find_commit_sha<'r>(repo: &'r Repository, sha: &str) -> anyhow::Result<Commit<'r>> {
+    repo.revparse_single(sha)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| anyhow::anyhow!("{} has no commit {sha}", repository_root(repo).display()))
+}
+
+/// The directory the repository was found in, the working tree or for a bare repository the repository itself
+pub fn repository_root(repo: &Repository) -> &Path {
+    if repo.is_bare() {
+        repo.path().components().as_path()
+    } else {
+        repo.path().parent().unwrap_or_else(|| repo.path())
+    }
+}
+
+/// The tip of the branch origin's HEAD points at, or of the branch named by init.defaultBranch
+fn default_branch(repo: &Repository) -> Option<Commit<'_>> {
+    let mut candidates = Vec::new();
+    if let Some(target) = repo
+        .find_reference(REMOTE_DEFAULT_BRANCH_REF)
+        .ok()
+        .and_then(|reference| reference.symbolic_target().map(str::to_string))
+    {
+        // The local branch may have commits that haven't been pushed yet
+        if let Some(name) = target.strip_prefix("refs/remotes/origin/") {
+            candidates.push(format!("refs/heads/{name}"));
+        }
+        candidates.push(target);
+    }
+    if let Ok(name) = repo
+        .config()
+        .and_then(|config| config.get_string("init.defaultBranch"))
+    {
+        candidates.push(format!("refs/heads/{name}"));
+    }
+    candidates.iter().find_map(|name| {
+        repo.find_reference(name)
+            .and_then(|reference| reference.peel_to_commit())
+            .ok()
+    })
+}
+
 /// Compute the diff between two trees and return a list of changes
 fn compute_diff(
-    root_path: &PathBuf,
+    root_path: &Path,
     repo: &Repository,
     commit: &Commit<'_>,
-    limit: Option<usize>,
+    mailmap: Option<&Mailmap>,
+    lfs_filter: Option<&Arc<GlobSet>>,
+    repo_marker: Option<&RepoMarker>,
+    config: &GitLogConfig,
 ) -> anyhow::Result<Vec<GourceLogFormat>> {
-    let a = if commit.parents().len() == 1 {
-        let parent = commit.parent(0)?;
-        Some(parent.tree()?)
-    } else {
+    let is_root = commit.parents().len() == 0;
+    if is_root && config.root_commit_mode == RootCommitMode::Skip {
+        return Ok(vec![]);
+    }
+
+    // Merges are diffed against their first parent, like `git log --diff-merges=first-parent`
+    let a = if is_root {
         None
+    } else {
+        Some(commit.parent(0)?.tree()?)
     };
 
+    let info = CommitInfo::from_commit(root_path, repo, commit, mailmap, config)?
+        .with_lfs_filter(lfs_filter.cloned());
     let b = commit.tree()?;
-    let diff = repo.diff_tree_to_tree(a.as_ref(), Some(&b), None)?;
-    let iter = diff.deltas().filter_map(|d| {
-        GourceLogFormat::try_from_delta(root_path, repo, commit, &d).unwrap_or_else(|e| {
-            error!("{e}");
-            None
-        })
-    });
+    let mut diff = repo.diff_tree_to_tree(a.as_ref(), Some(&b), None)?;
+    find_similar(&mut diff, config)?;
+    let changes = if diff.deltas().len() == 0 && config.keep_empty_commits {
+        info.empty_commit_marker(config).into_iter().collect()
+    } else {
+        let mut changes: Vec<GourceLogFormat> = diff
+            .deltas()
+            .flat_map(|d| {
+                GourceLogFormat::try_from_delta(&info, &d, config).unwrap_or_else(|e| {
+                    record(Warning::PathUnreadable, e);
+                    vec![]
+                })
+            })
+            .collect();
+        set_changeset_size(&mut changes, diff.deltas().len(), config);
+        changes
+    };
+
+    let changes = finish_changes(&info, changes, config);
+    if let Some(repo_marker) = repo_marker {
+        repo_marker.record(&info, &changes);
+    }
+    Ok(changes)
+}
+
+/// Detect renames and copies in a commit's diff when they're shown differently from a delete and an add.
+/// Finding copies finds renames too, the same as `git log --find-copies`
+fn find_similar(diff: &mut git2::Diff<'_>, config: &GitLogConfig) -> anyhow::Result<()> {
+    if config.detect_renames() || config.detect_copies() {
+        diff.find_similar(Some(
+            DiffFindOptions::new()
+                .renames(true)
+                .copies(config.detect_copies()),
+        ))?;
+    }
+    Ok(())
+}
 
-    if let Some(limit) = limit {
-        let c: Vec<GourceLogFormat> = iter.take(limit + 1).collect();
-        if c.len() > limit {
-            return Ok(vec![]);
+/// Record the total number of files the commit changed on each of its records,
+/// this is counted before any filters are applied
+pub fn set_changeset_size(changes: &mut [GourceLogFormat], size: usize, config: &GitLogConfig) {
+    if config.emit_changeset_size {
+        for change in changes {
+            change.changeset_size = Some(size as u64);
         }
-        Ok(c)
-    } else {
-        Ok(iter.collect())
+    }
+}
+
+/// Record where the commit came in the walk, for --preserve-commit-order,
+/// a newest first walk counts down so the sequence is always higher later in history
+pub fn set_sequence(
+    changes: &mut [GourceLogFormat],
+    index: usize,
+    newest_first: bool,
+    config: &GitLogConfig,
+) {
+    if config.preserve_commit_order {
+        let index = i64::try_from(index).unwrap_or(i64::MAX);
+        let sequence = if newest_first { -index } else { index };
+        for change in changes {
+            change.sequence = Some(sequence);
+        }
+    }
+}
+
+/// Everything done to a commit's changes once they've been read: the limits,
+/// then the copies for the committer and co-authors, then `--dedupe-within-commit`
+pub fn finish_changes(
+    info: &CommitInfo,
+    changes: Vec<GourceLogFormat>,
+    config: &GitLogConfig,
+) -> Vec<GourceLogFormat> {
+    let changes = info.add_shared_changes(limit_changes(changes, info.is_root, config));
+    dedupe_within_commit(changes, config)
+}
+
+/// Drop repeats of the same user doing the same thing to the same file in a single commit,
+/// comparing usernames after the global aliases since that's what ends up in the log
+fn dedupe_within_commit(
+    mut changes: Vec<GourceLogFormat>,
+    config: &GitLogConfig,
+) -> Vec<GourceLogFormat> {
+    if config.dedupe_within_commit {
+        let mut seen = HashSet::with_capacity(changes.len());
+        changes.retain(|change| {
+            let username = config.comparable_username(&change.username);
+            seen.insert((username, change.file.clone(), change.r#type))
+        });
+    }
+    changes
+}
+
+/// Apply the per-commit limits to the changes from a single commit
+pub fn limit_changes(
+    mut changes: Vec<GourceLogFormat>,
+    is_root: bool,
+    config: &GitLogConfig,
+) -> Vec<GourceLogFormat> {
+    if is_root && config.root_commit_mode == RootCommitMode::Summarize {
+        // Many files collapse into the same top-level entry
+        changes.sort_unstable();
+        changes.dedup();
+    }
+
+    if config
+        .max_changeset_size
+        .is_some_and(|limit| changes.len() > limit)
+    {
+        return vec![];
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_similar, get_commit_count, start_commit};
+    use crate::test_support::{config, lines, read, two_branches, Fixture};
+    use git2::Delta;
+
+    const POEM: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+
+    #[test]
+    fn copies_are_detected_and_added_with_copy_as_added() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit("Ann", 100, &[("a.txt", Some(POEM))])?;
+        // git only looks for copies of files changed in the same commit
+        let copy = fixture.commit(
+            "Bob",
+            200,
+            &[
+                ("a.txt", Some(&format!("{POEM}eleven\n"))),
+                ("b.txt", Some(POEM)),
+            ],
+        )?;
+
+        let commit = fixture.repo().find_commit(copy)?;
+        let mut diff = fixture.repo().diff_tree_to_tree(
+            Some(&commit.parent(0)?.tree()?),
+            Some(&commit.tree()?),
+            None,
+        )?;
+        find_similar(&mut diff, &config(&fixture.path(), &["--copy-as-added"])?)?;
+        assert!(diff.deltas().any(|d| d.status() == Delta::Copied));
+
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(&fixture.path(), &["--backend", backend, "--copy-as-added"])?;
+            assert_eq!(
+                lines(&logs),
+                ["100|Ann|A|a.txt", "200|Bob|M|a.txt", "200|Bob|A|b.txt"],
+                "{backend}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn renames_are_a_delete_and_an_add_at_the_same_time() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit("Ann", 100, &[("a/x", Some(POEM))])?;
+        let rename = fixture.commit("Bob", 200, &[("a/x", None), ("b/x", Some(POEM))])?;
+
+        let commit = fixture.repo().find_commit(rename)?;
+        let mut diff = fixture.repo().diff_tree_to_tree(
+            Some(&commit.parent(0)?.tree()?),
+            Some(&commit.tree()?),
+            None,
+        )?;
+        find_similar(&mut diff, &config(&fixture.path(), &["--detect-renames"])?)?;
+        assert!(diff.deltas().any(|d| d.status() == Delta::Renamed));
+
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(&fixture.path(), &["--backend", backend, "--detect-renames"])?;
+            assert_eq!(
+                lines(&logs),
+                ["100|Ann|A|a/x", "200|Bob|D|a/x", "200|Bob|A|b/x"],
+                "{backend}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn skip_lfs_drops_files_tracked_by_lfs() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit(
+            "Ann",
+            100,
+            &[
+                (
+                    ".gitattributes",
+                    Some("*.png filter=lfs diff=lfs merge=lfs -text\n"),
+                ),
+                ("a.txt", Some("one")),
+                (
+                    "media/logo.png",
+                    Some("version https://git-lfs.github.com/spec/v1\n"),
+                ),
+            ],
+        )?;
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(&fixture.path(), &["--backend", backend, "--skip-lfs"])?;
+            assert_eq!(
+                lines(&logs),
+                ["100|Ann|A|.gitattributes", "100|Ann|A|a.txt"],
+                "{backend}"
+            );
+            let logs = read(&fixture.path(), &["--backend", backend])?;
+            assert_eq!(logs.len(), 3, "{backend}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn excluded_commits_are_left_out() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit("Ann", 100, &[("a.txt", Some("one"))])?;
+        let bad = fixture.commit("Bob", 200, &[("junk.txt", Some("two"))])?;
+        let reverted = fixture.commit("Bob", 300, &[("junk.txt", None)])?;
+        fixture.commit("Ann", 400, &[("a.txt", Some("three"))])?;
+
+        let commits = tempfile::NamedTempFile::new()?;
+        std::fs::write(
+            commits.path(),
+            format!("# Reverted\n\n{:.8}\n", reverted.to_string()),
+        )?;
+        let commits = commits.path().to_string_lossy();
+        let bad = bad.to_string();
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(
+                &fixture.path(),
+                &[
+                    "--backend",
+                    backend,
+                    "--exclude-commit",
+                    &bad,
+                    "--exclude-commits-file",
+                    &commits,
+                ],
+            )?;
+            assert_eq!(
+                lines(&logs),
+                ["100|Ann|A|a.txt", "400|Ann|M|a.txt"],
+                "{backend}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn since_keeps_newer_commits_after_a_skewed_one() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit("Ann", 1_700_000_000, &[("a.txt", Some("one"))])?;
+        fixture.commit("Ann", 1_700_100_000, &[("p.txt", Some("two"))])?;
+        // Made on a machine with its clock behind
+        fixture.commit("Bob", 1_700_030_000, &[("s.txt", Some("three"))])?;
+        fixture.commit("Ann", 1_700_200_000, &[("top.txt", Some("four"))])?;
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(
+                &fixture.path(),
+                &["--backend", backend, "--since", "1700050000"],
+            )?;
+            assert_eq!(
+                lines(&logs),
+                ["1700100000|Ann|A|p.txt", "1700200000|Ann|A|top.txt"],
+                "{backend}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn all_refs_reads_commits_only_on_other_branches() -> anyhow::Result<()> {
+        let fixture = two_branches()?;
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(&fixture.path(), &["--backend", backend])?;
+            assert_eq!(
+                lines(&logs),
+                ["100|Ann|A|a.txt", "300|Ann|M|a.txt"],
+                "{backend}"
+            );
+            let logs = read(&fixture.path(), &["--backend", backend, "--all-refs"])?;
+            assert_eq!(
+                lines(&logs),
+                [
+                    "100|Ann|A|a.txt",
+                    "200|Bob|A|feature.txt",
+                    "300|Ann|M|a.txt"
+                ],
+                "{backend} --all-refs"
+            );
+        }
+
+        // The progress bar's total matches what's read
+        for (args, count) in [(&[][..], 2), (&["--all-refs"][..], 3)] {
+            let config = config(&fixture.path(), args)?;
+            let start = start_commit(fixture.repo(), &config)?.id();
+            assert_eq!(get_commit_count(fixture.repo(), start, &config)?, count);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merge_is_diffed_against_its_first_parent() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit("Ann", 100, &[("a.txt", Some("one"))])?;
+        fixture.branch("feature")?;
+        fixture.checkout("feature")?;
+        fixture.commit("Bob", 200, &[("b.txt", Some("two"))])?;
+        fixture.checkout("main")?;
+        fixture.commit("Ann", 300, &[("a.txt", Some("three"))])?;
+        fixture.merge("Cat", 400, "feature")?;
+
+        // A bare clone has the same history, read from the repository directory itself
+        let bare = tempfile::tempdir()?;
+        git2::build::RepoBuilder::new()
+            .bare(true)
+            .clone(&fixture.path().to_string_lossy(), bare.path())?;
+        let bare = bare.path().canonicalize()?;
+
+        for (path, backend) in [
+            (fixture.path(), "libgit2"),
+            (fixture.path(), "git-cli"),
+            (bare.clone(), "libgit2"),
+            (bare, "git-cli"),
+        ] {
+            let logs = read(&path, &["--backend", backend])?;
+            assert_eq!(
+                lines(&logs),
+                [
+                    "100|Ann|A|a.txt",
+                    "200|Bob|A|b.txt",
+                    "300|Ann|M|a.txt",
+                    "400|Cat|A|b.txt",
+                ],
+                "{} with {backend}",
+                path.display()
+            );
+        }
+        Ok(())
     }
 }
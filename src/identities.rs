@@ -0,0 +1,87 @@
+use crate::consts::{IDENTITY_REPORT_LIMIT, IDENTITY_WARNING_THRESHOLD};
+use crate::warnings;
+use log::warn;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+/// Every email address seen for each author name, to spot people committing under several identities
+static IDENTITIES: Mutex<Option<HashMap<String, BTreeSet<String>>>> = Mutex::new(None);
+
+/// Remember that an author committed under this name and email, authors without an email are ignored
+pub fn record(name: &str, email: Option<&str>) {
+    let Some(email) = email.map(str::trim).filter(|email| !email.is_empty()) else {
+        return;
+    };
+    if !warnings::enabled() {
+        return;
+    }
+    let email = email.to_lowercase();
+    if let Ok(mut identities) = IDENTITIES.lock() {
+        identities
+            .get_or_insert_with(HashMap::new)
+            .entry(name.to_string())
+            .or_default()
+            .insert(email);
+    }
+}
+
+/// Warn about the names used with the most emails and the emails used with the most names.
+/// Names are compared after the aliases, so identities that are already merged aren't reported,
+/// and in lowercase with `case_insensitive` since every casing ends up as the same name
+pub fn log_summary(aliases: &HashMap<String, String>, case_insensitive: bool) {
+    let Some(identities) = IDENTITIES.lock().ok().and_then(|mut i| i.take()) else {
+        return;
+    };
+    let identities = identities
+        .into_iter()
+        .map(|(name, emails)| {
+            let name = if case_insensitive {
+                name.to_lowercase()
+            } else {
+                name
+            };
+            (aliases.get(&name).cloned().unwrap_or(name), emails)
+        })
+        .collect::<Vec<_>>();
+
+    let mut emails_by_name: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    let mut names_by_email: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for (name, emails) in &identities {
+        let name = name.as_str();
+        for email in emails {
+            emails_by_name.entry(name).or_default().insert(email);
+            names_by_email.entry(email).or_default().insert(name);
+        }
+    }
+
+    for (name, emails) in worst(&emails_by_name) {
+        warn!(
+            "{name} commits under {} email addresses, a .mailmap entry can tidy them up",
+            emails.len()
+        );
+    }
+    for (email, names) in worst(&names_by_email) {
+        let names = names.iter().copied().collect::<Vec<_>>();
+        warn!(
+            "{email} commits as {} different names ({}), consider an alias such as --alias '{}::{}'",
+            names.len(),
+            names.join(", "),
+            names[names.len() - 1],
+            names[0]
+        );
+    }
+}
+
+/// The entries with at least `IDENTITY_WARNING_THRESHOLD` identities, most first
+fn worst<'a>(
+    identities: &'a HashMap<&'a str, BTreeSet<&'a str>>,
+) -> Vec<(&'a str, &'a BTreeSet<&'a str>)> {
+    let mut worst = identities
+        .iter()
+        .filter(|(_, others)| others.len() >= IDENTITY_WARNING_THRESHOLD)
+        .map(|(key, others)| (*key, others))
+        .collect::<Vec<_>>();
+    worst.sort_unstable_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+    worst.truncate(IDENTITY_REPORT_LIMIT);
+    worst
+}
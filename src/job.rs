@@ -0,0 +1,290 @@
+use crate::cli::ClapArguments;
+use crate::structs::{GourceActionType, RepositoryOptions, RootCommitMode};
+use crate::validation::parse_date;
+use anyhow::{anyhow, bail, Context};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A whole run described in JSON, for `--job`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobSpec {
+    /// Same as --path, the prefixes of repositories without one are relative to this
+    path: Option<String>,
+    #[serde(default)]
+    recursive: bool,
+    /// The repositories to read, instead of discovering them under `path`
+    #[serde(default)]
+    repositories: Vec<JobRepository>,
+    /// Applied to every repository, the same as --alias
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+    /// Same as --alias-file, `aliases` are applied after it so they win
+    alias_file: Option<String>,
+    #[serde(default)]
+    filters: JobFilters,
+    #[serde(default)]
+    output: JobOutput,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobRepository {
+    path: String,
+    /// Replaces the repository's path in the log
+    prefix: Option<String>,
+    /// Seconds added to the time of every commit
+    #[serde(default)]
+    time_offset: i64,
+    /// Applied before the global aliases, to this repository only
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobFilters {
+    max_changeset_size: Option<usize>,
+    only_types: Option<Vec<GourceActionType>>,
+    sensible_defaults: Option<bool>,
+    skip_mode_only_changes: Option<bool>,
+    root_commit_mode: Option<RootCommitMode>,
+    skip_lfs: Option<bool>,
+    skip_recent: Option<usize>,
+    repo_active_since: Option<String>,
+    /// Map names through each repository's .mailmap, the opposite of --no-mailmap
+    mailmap: Option<bool>,
+    /// Attribute commits to their co-authors too, the opposite of --no-co-authors
+    co_authors: Option<bool>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobOutput {
+    file: Option<String>,
+    split_by_author: Option<String>,
+    use_merge_sort: Option<bool>,
+    header_comment: Option<String>,
+    no_header: Option<bool>,
+    stats: Option<String>,
+}
+
+/// The repositories listed in a job, with their options
+pub struct JobRepositories {
+    pub root: PathBuf,
+    pub repositories: Vec<PathBuf>,
+    pub options: HashMap<PathBuf, RepositoryOptions>,
+}
+
+impl JobSpec {
+    /// Read and validate a job file, errors name the field that's wrong
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents =
+            fs::read_to_string(path).context(format!("Failed to read job {}", path.display()))?;
+        let mut deserializer = serde_json::Deserializer::from_str(&contents);
+        let job: Self = serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+            anyhow!(
+                "Invalid job {}: {}: {}",
+                path.display(),
+                e.path(),
+                e.inner()
+            )
+        })?;
+        job.validate()
+            .map_err(|e| anyhow!("Invalid job {}: {e}", path.display()))?;
+        Ok(job)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.path.is_none() && self.repositories.is_empty() {
+            bail!("either path or repositories must be given");
+        }
+        if self.recursive && !self.repositories.is_empty() {
+            bail!("recursive: can't be used with repositories");
+        }
+        if self.alias_file.as_ref().is_some_and(String::is_empty) {
+            bail!("alias_file: must not be empty");
+        }
+        for (index, repository) in self.repositories.iter().enumerate() {
+            if repository.path.is_empty() {
+                bail!("repositories[{index}].path: must not be empty");
+            }
+            if repository.prefix.as_ref().is_some_and(String::is_empty) {
+                bail!("repositories[{index}].prefix: must not be empty");
+            }
+        }
+        if let Some(date) = &self.filters.repo_active_since {
+            parse_date(date).map_err(|e| anyhow!("filters.repo_active_since: {e}"))?;
+        }
+        if self.output.file.is_some() && self.output.split_by_author.is_some() {
+            bail!("output.split_by_author: can't be used with output.file");
+        }
+        if self.output.no_header == Some(true) && self.output.header_comment.is_some() {
+            bail!("output.no_header: can't be used with output.header_comment");
+        }
+        Ok(())
+    }
+
+    /// Apply the job's settings to the arguments, these override the command line
+    pub fn apply(&self, args: &mut ClapArguments) -> anyhow::Result<()> {
+        if let Some(path) = &self.path {
+            args.path = Some(path.clone());
+        }
+        args.recursive |= self.recursive;
+        args.alias.extend(
+            self.aliases
+                .iter()
+                .map(|(from, to)| format!("{from}::{to}")),
+        );
+        if let Some(path) = &self.alias_file {
+            args.alias_file = Some(path.clone());
+        }
+
+        let filters = &self.filters;
+        if let Some(size) = filters.max_changeset_size {
+            args.max_changeset_size = Some(size);
+        }
+        if let Some(types) = &filters.only_types {
+            args.only_types.clone_from(types);
+        }
+        if let Some(enabled) = filters.sensible_defaults {
+            args.sensible_defaults = enabled;
+        }
+        if let Some(enabled) = filters.skip_mode_only_changes {
+            args.skip_mode_only_changes = enabled;
+        }
+        if let Some(mode) = filters.root_commit_mode {
+            args.root_commit_mode = mode;
+        }
+        if let Some(enabled) = filters.skip_lfs {
+            args.skip_lfs = enabled;
+        }
+        if let Some(count) = filters.skip_recent {
+            args.skip_recent = Some(count);
+        }
+        if let Some(date) = &filters.repo_active_since {
+            args.repo_active_since = Some(parse_date(date).map_err(|e| anyhow!(e))?);
+        }
+        if let Some(enabled) = filters.mailmap {
+            args.no_mailmap = !enabled;
+        }
+        if let Some(enabled) = filters.co_authors {
+            args.no_co_authors = !enabled;
+        }
+
+        let output = &self.output;
+        if let Some(file) = &output.file {
+            args.output = Some(file.clone());
+            args.split_by_author = None;
+        }
+        if let Some(directory) = &output.split_by_author {
+            args.split_by_author = Some(directory.clone());
+            args.output = None;
+        }
+        if let Some(enabled) = output.use_merge_sort {
+            args.use_merge_sort = enabled;
+            args.auto_merge_sort &= !enabled;
+        }
+        if let Some(comment) = &output.header_comment {
+            // --no-header on the command line still turns it off
+            args.header_comment = Some(comment.clone());
+        }
+        if let Some(enabled) = output.no_header {
+            args.no_header = enabled;
+        }
+        if let Some(stats) = &output.stats {
+            args.stats = Some(stats.clone());
+        }
+        Ok(())
+    }
+
+    /// The repositories listed in the job, if there are any. Without a path to be relative to,
+    /// repositories without a prefix are named after their directory
+    pub fn repositories(&self) -> anyhow::Result<Option<JobRepositories>> {
+        if self.repositories.is_empty() {
+            return Ok(None);
+        }
+        let root = match &self.path {
+            Some(path) => canonical(path).context("Invalid job: path")?,
+            None => std::env::current_dir()?,
+        };
+
+        let mut repositories = Vec::with_capacity(self.repositories.len());
+        let mut options = HashMap::new();
+        for (index, repository) in self.repositories.iter().enumerate() {
+            let path = canonical(&repository.path)
+                .context(format!("Invalid job: repositories[{index}].path"))?;
+            let prefix = match &repository.prefix {
+                Some(prefix) => Some(prefix.trim_matches('/').to_string()),
+                None if self.path.is_none() => path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned()),
+                None => {
+                    if !path.starts_with(&root) {
+                        bail!(
+                            "Invalid job: repositories[{index}].path: {} is not under {}, give it a prefix",
+                            path.display(),
+                            root.display()
+                        );
+                    }
+                    None
+                }
+            };
+            let repository_options = RepositoryOptions {
+                prefix,
+                time_offset: repository.time_offset,
+                aliases: repository.aliases.clone(),
+                excludes: None,
+            };
+            if options.insert(path.clone(), repository_options).is_some() {
+                bail!(
+                    "Invalid job: repositories[{index}].path: {} is listed more than once",
+                    path.display()
+                );
+            }
+            repositories.push(path);
+        }
+        Ok(Some(JobRepositories {
+            root,
+            repositories,
+            options,
+        }))
+    }
+}
+
+fn canonical(path: &str) -> anyhow::Result<PathBuf> {
+    PathBuf::from(&*shellexpand::tilde(path))
+        .canonicalize()
+        .context(format!("{path} does not exist"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JobSpec;
+    use crate::test_support::arguments;
+
+    #[test]
+    fn no_header_turns_off_the_jobs_header_comment() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("job.json");
+        std::fs::write(
+            &path,
+            r#"{"path": ".", "output": {"header_comment": "Nightly"}}"#,
+        )?;
+        let job = JobSpec::load(&path)?;
+
+        let mut args = arguments(dir.path(), &[])?;
+        job.apply(&mut args)?;
+        assert_eq!(
+            crate::build_header(&args, None).last().map(String::as_str),
+            Some("Nightly")
+        );
+
+        let mut args = arguments(dir.path(), &["--no-header"])?;
+        job.apply(&mut args)?;
+        assert!(crate::build_header(&args, None).is_empty());
+        Ok(())
+    }
+}
@@ -16,7 +16,7 @@
     clippy::print_stderr,
     clippy::print_stdout,
     clippy::same_name_method,
-    clippy::string_to_string,
+    clippy::implicit_clone,
     clippy::todo,
     clippy::try_err,
     clippy::unimplemented,
@@ -26,120 +26,663 @@
     clippy::wildcard_enum_match_arm
 )]
 
+mod activity;
 mod cli;
+mod config;
 mod consts;
+mod exit;
+mod final_state;
+mod git_cli;
 mod git_stuff;
+mod identities;
+mod job;
+mod output;
+mod progress;
+mod repo_marker;
 mod serde;
+mod source;
+mod stats;
+mod store;
 mod structs;
+#[cfg(test)]
+mod test_support;
 mod validation;
+mod warnings;
 
 use crate::serde::DiskLogReader;
-use anyhow::Context;
-use clap::Parser;
+use anyhow::{anyhow, bail, Context};
 use cli::ClapArguments;
-use csv::QuoteStyle;
 use ext_sort::buffer::mem::MemoryLimitedBufferBuilder;
-use ext_sort::{ExternalSorter, ExternalSorterBuilder};
+use ext_sort::{ChunkBufferBuilder, ExternalSorter, ExternalSorterBuilder, LimitedBufferBuilder};
 
-use crate::consts::{DEFAULT_PROGRESS_STYLE, DEFAULT_SPINNER_STYLE, DEFAULT_SPINNER_TICK_STYLE};
-use crate::structs::{GourceLogConfig, MergeSortConfig};
+use crate::activity::{ActivityConfig, ActivityWriter};
+use crate::consts::{
+    AVATAR_EXTENSIONS, AVATAR_MANIFEST_FILENAME, CHECKPOINT_SUFFIX, DEFAULT_MEMORY_LIMIT_MB,
+    PROGRESS_SOCKET_INTERVAL_MS,
+};
+use crate::exit::ExitError;
+use crate::job::{JobRepositories, JobSpec};
+use crate::output::{partial_output_path, OutputCompression, OutputWriter};
+use crate::progress::{Progress, ProgressReporter, ProgressStyles};
+use crate::stats::{Stats, StatsConfig};
+use crate::store::LogStore;
+use crate::structs::{
+    escape_line_breaks, AliasPattern, AuthorFilter, AuthorNames, DirectoryCap, DirectoryTree,
+    GitLogConfig, GourceLogConfig, Identity, MergeSortConfig, StartPoint,
+};
 use consts::TEMPORARY_LOG_FILENAME;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use indicatif_log_bridge::LogWrapper;
-use log::warn;
-use rayon::prelude::*;
-use std::collections::HashMap;
+use log::{debug, info, warn};
+use std::collections::{BTreeSet, HashMap};
 use std::io::Write;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
-use std::time::Duration;
+use std::process::{Command, ExitCode};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{fs, io};
 use structs::GourceLogFormat;
 
-fn main() -> anyhow::Result<()> {
+#[allow(clippy::print_stderr)]
+// Reason: Reports the error the same way returning it from main would, but with our exit code
+fn main() -> ExitCode {
     reset_pipe();
-    let args = ClapArguments::parse();
+    let args = match config::parse_arguments() {
+        Ok(args) => args,
+        Err(e) if e.use_stderr() => {
+            // Usage errors get the same exit code as any other invalid argument
+            let _ = e.print();
+            return ExitCode::from(ExitError::InvalidArguments.code());
+        }
+        Err(e) => e.exit(),
+    };
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exit::exit_code(&e))
+        }
+    }
+}
+
+fn run(mut args: ClapArguments) -> anyhow::Result<()> {
+    let job_repositories = load_job(&mut args).context(ExitError::InvalidArguments)?;
+
+    let multi = init_logging(&args)?;
+    warnings::set_verbose(args.verbose);
+    if args.no_warnings {
+        warnings::disable();
+    }
+
+    let progress_reporter = match (&args.progress_socket, args.progress_interval) {
+        (Some(path), _) => Some(progress_socket_reporter(&multi, path)?),
+        (None, Some(seconds)) => Some(progress_interval_reporter(&multi, seconds)),
+        (None, None) => None,
+    };
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.get())
+            .build_global()?;
+    }
+
+    // Parse and validate the arguments, then discover the repositories
+    let aliases = load_aliases(&args).context(ExitError::InvalidArguments)?;
+    validation::validate_field_order(&args.field_order).context(ExitError::InvalidArguments)?;
+    validation::validate_date_range(args.since, args.until).context(ExitError::InvalidArguments)?;
+    let mut git_log_config = build_git_log_config(&args, &aliases, job_repositories.as_ref())
+        .context(ExitError::InvalidArguments)?;
+    let repositories = discover(
+        &args,
+        job_repositories,
+        &mut git_log_config,
+        &aliases,
+        &multi,
+    )?;
+    if args.dry_validate {
+        let count = repositories.as_ref().map_or(0, |(_, r)| r.len());
+        info!("Everything looks valid, {count} repositories would be read");
+        return Ok(());
+    }
+
+    // Cleanup any previous runs if they exist
+    if Path::new(TEMPORARY_LOG_FILENAME).exists() {
+        fs::remove_file(TEMPORARY_LOG_FILENAME).context("Failed to remove temp file")?;
+    }
+
+    if let (Some(path), Some((_, repositories))) = (&args.dump_authors, &repositories) {
+        return dump_authors(path, repositories, args.use_email, &git_log_config);
+    }
+
+    let merge_sort_config = build_merge_sort_config(&args)?;
+    let store = build_log_store(&args, merge_sort_config.as_ref())?;
+
+    read_logs(
+        &args,
+        repositories.as_ref(),
+        &store,
+        &multi,
+        &git_log_config,
+    )?;
+    if let (Some(path), Some(config)) = (&args.append_temp, &merge_sort_config) {
+        if let Some(reporter) = progress_reporter {
+            reporter.stop();
+        }
+        return finish_append(store, path, config, &aliases, args.case_insensitive_authors);
+    }
+
+    warn_on_large_output(&store, args.warn_output_size, args.use_merge_sort)?;
+    let header = build_header(&args, repositories.as_ref().map(|(_, r)| r.len()));
+    let temp_path = merge_sort_config.as_ref().map(|c| c.tmp_location.clone());
+    // Only merge sort if the logs actually ended up on disk
+    let merge_sort_config = merge_sort_config.filter(|_| store.is_on_disk());
+    let logs = store.finish()?;
+
+    // Do the final sort and write out the log file
+    let gource_log_config = build_gource_log_config(
+        &args,
+        aliases,
+        git_log_config.alias_patterns.clone(),
+        header,
+        merge_sort_config,
+    );
+    write_gource_log(logs, &multi, &gource_log_config)?;
+
+    if let Some(reporter) = progress_reporter {
+        reporter.stop();
+    }
+    warnings::log_summary();
+    identities::log_summary(
+        &gource_log_config.aliases,
+        gource_log_config.case_insensitive_authors,
+    );
+
+    if args.verify_sort {
+        if let Some(output) = &args.output {
+            let output = Path::new(output);
+            validation::verify_sort(
+                output,
+                OutputCompression::for_output(args.compress, Some(output)),
+                &args.field_order,
+                args.preserve_commit_order,
+            )?;
+        }
+    }
+
+    if args.preview {
+        if let Some(output) = &args.output {
+            preview_in_gource(output, args.gource_args.as_deref())?;
+        }
+    }
+
+    // Cleanup if needed
+    if let Some(path) = temp_path {
+        remove_temporary_files(&path)?;
+    }
 
-    // Setup logging
+    Ok(())
+}
+
+fn init_logging(args: &ClapArguments) -> anyhow::Result<Progress> {
     let mut logger = env_logger::Builder::from_env(
-        env_logger::Env::default()
-            .default_filter_or("info")
-            .filter("ext_sort=warn"),
+        env_logger::Env::default().default_filter_or("info,ext_sort=warn"),
     );
 
+    if args.quiet {
+        logger.filter_level(log::LevelFilter::Warn);
+    }
     // If we're writing to stdout, disable logging
-    if args.output.is_none() {
+    if args.output.is_none()
+        && args.split_by_author.is_none()
+        && args.dump_authors.is_none()
+        && args.append_temp.is_none()
+        && !args.dry_validate
+    {
         logger.filter_level(log::LevelFilter::Off);
     }
 
     let logger = logger.build();
 
-    let multi = MultiProgress::new();
-    LogWrapper::new(multi.clone(), logger).try_init()?;
+    let styles = ProgressStyles::new(
+        args.progress_template.as_deref(),
+        args.spinner_template.as_deref(),
+    )
+    .context(ExitError::InvalidArguments)?;
+    let multi = Progress::new(styles);
+    LogWrapper::new(multi.multi().clone(), logger).try_init()?;
+    if args.quiet {
+        multi.hide();
+    }
+    Ok(multi)
+}
 
-    // Cleanup any previous runs if they exist
-    if Path::new(TEMPORARY_LOG_FILENAME).exists() {
-        fs::remove_file(TEMPORARY_LOG_FILENAME).context("Failed to remove temp file")?;
+/// Write every author in the repositories to a file, instead of generating the log
+fn dump_authors(
+    path: &str,
+    repositories: &[PathBuf],
+    use_email: bool,
+    config: &GitLogConfig,
+) -> anyhow::Result<()> {
+    let authors = git_stuff::read_authors(repositories, use_email, config)?;
+    let path = PathBuf::from(&*shellexpand::tilde(path));
+    let mut writer = io::BufWriter::new(
+        fs::File::create(&path).context("Failed to create --dump-authors file")?,
+    );
+    for author in &authors {
+        if use_email {
+            writeln!(writer, "{author}")?;
+        } else {
+            // A stub alias, ready to have the right hand side filled in
+            writeln!(writer, "{author}::{author}")?;
+        }
     }
+    writer.flush()?;
+    info!("Wrote {} authors to {}", authors.len(), path.display());
+    Ok(())
+}
 
-    // Parse and validate the arguments, then discover the repositories
-    let root = PathBuf::from(&*shellexpand::tilde(&args.path)).canonicalize()?;
-    let aliases = validation::validate_aliases(&args.alias)?;
-    let repositories =
-        validation::discover_repositories(&root, args.recursive, &args.include, &args.exclude)?;
-    let repositories = validation::validate_repositories(repositories);
-
-    #[allow(clippy::if_then_some_else_none)]
-    // Reason: We can't use ? inside a closure
-    let (merge_sort_config, locked_output_writer) = if args.use_merge_sort {
-        let config = MergeSortConfig::new(args.sort_chunk_size, args.temp_file_location)?;
-
-        let writer = Mutex::new(io::BufWriter::new(
-            fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(config.tmp_location.join(TEMPORARY_LOG_FILENAME))?,
-        ));
+/// Warn before the write phase if the log looks like it's going to be very large
+fn warn_on_large_output(
+    store: &LogStore,
+    threshold_gb: f64,
+    use_merge_sort: bool,
+) -> anyhow::Result<()> {
+    if !warnings::enabled() {
+        return Ok(());
+    }
+    #[allow(clippy::cast_precision_loss)]
+    // Reason: Only compared against a threshold and shown to one decimal place
+    let size_gb = store.size()? as f64 / 1024.0 / 1024.0 / 1024.0;
+    if size_gb > threshold_gb {
+        let suggestion = if use_merge_sort || store.is_on_disk() {
+            "consider filtering it, e.g. with --sensible-defaults, --only-types or --max-changeset-size"
+        } else {
+            "consider --use-merge-sort or filtering it, e.g. with --sensible-defaults, --only-types or --max-changeset-size"
+        };
+        warn!("The log is around {size_gb:.1} GB, {suggestion}");
+    }
+    Ok(())
+}
+
+/// Collect the aliases from --alias-file and --alias,
+/// the ones given on the command line replace the file's
+fn load_aliases(args: &ClapArguments) -> anyhow::Result<HashMap<String, String>> {
+    let alias_file = args
+        .alias_file
+        .as_ref()
+        .map(|path| validation::read_alias_file(Path::new(&*shellexpand::tilde(path))))
+        .transpose()?
+        .unwrap_or_default();
+    let aliases = validation::validate_aliases(
+        alias_file
+            .iter()
+            .map(|(alias, origin)| (alias.as_str(), origin.clone()))
+            .chain(
+                args.alias
+                    .iter()
+                    .map(|alias| (alias.as_str(), "given with --alias".to_string())),
+            ),
+    )?;
+    if args.case_insensitive_authors {
+        return Ok(aliases
+            .into_iter()
+            .map(|(from, to)| (from.to_lowercase(), to))
+            .collect());
+    }
+    Ok(aliases)
+}
+
+/// Apply the settings from the --job file to the arguments and return the repositories it lists
+fn load_job(args: &mut ClapArguments) -> anyhow::Result<Option<JobRepositories>> {
+    let Some(path) = &args.job else {
+        return Ok(None);
+    };
+    let job = JobSpec::load(Path::new(&*shellexpand::tilde(path)))?;
+    job.apply(args)?;
+    job.repositories()
+}
 
-        (Some(config), Some(writer))
+/// Find the repositories to read, along with the base path their files are relative to
+fn discover(
+    args: &ClapArguments,
+    job_repositories: Option<JobRepositories>,
+    config: &mut GitLogConfig,
+    aliases: &HashMap<String, String>,
+    multi: &Progress,
+) -> anyhow::Result<Option<(PathBuf, Vec<PathBuf>)>> {
+    let (root, repositories) = if let Some(job) = job_repositories {
+        (job.root, job.repositories)
     } else {
-        (None, None)
+        let Some(path) = &args.path else {
+            return Ok(None);
+        };
+        let root = PathBuf::from(&*shellexpand::tilde(path))
+            .canonicalize()
+            .context(format!("Invalid --path {path}"))
+            .context(ExitError::InvalidArguments)?;
+        // Scanning a large tree can take a while before the first repository bar appears
+        let include = validation::glob_filter("--include", &args.include)
+            .context(ExitError::InvalidArguments)?;
+        let exclude = validation::glob_filter("--exclude", &args.exclude)
+            .context(ExitError::InvalidArguments)?;
+        let spinner = multi.add_spinner();
+        spinner.set_prefix("Discovering repositories");
+        let repositories = validation::discover_repositories(
+            &root,
+            args.recursive,
+            args.max_depth,
+            include.as_ref(),
+            exclude.as_ref(),
+            &spinner,
+        )?;
+        spinner.finish_with_message(format!(
+            "{} directories scanned, {} repositories found",
+            spinner.position(),
+            repositories.len()
+        ));
+        (root, repositories)
     };
+    if repositories.is_empty() {
+        bail!(ExitError::NoRepositories);
+    }
+    let mut repositories = validation::validate_repositories(repositories, &config.start_point);
+    if args.use_git_config {
+        validation::read_git_config(&repositories, &mut config.repository_options, aliases)?;
+    }
+    if let Some(since) = args.repo_active_since {
+        repositories = validation::filter_inactive_repositories(repositories, since, config);
+    }
+    if repositories.is_empty() {
+        bail!(ExitError::AllRepositoriesSkipped);
+    }
+    validation::sort_repositories(&mut repositories, args.sort_repos);
 
-    let logs = repositories
-        .par_iter()
-        .map(|r| {
-            git_stuff::read_git_log(
-                &root,
-                r,
-                locked_output_writer.as_ref(),
-                &multi,
-                args.max_changeset_size,
-            )
+    let base = match &args.relative_to {
+        Some(base) => PathBuf::from(&*shellexpand::tilde(base))
+            .canonicalize()
+            .context(format!("Invalid --relative-to path {base}"))
+            .context(ExitError::InvalidArguments)?,
+        None => root,
+    };
+    // Repositories with their own prefix don't need to be under the base
+    if args.absolute_paths {
+        return Ok(Some((base, repositories)));
+    }
+    let unprefixed = repositories
+        .iter()
+        .filter(|path| {
+            config
+                .repository_options
+                .get(*path)
+                .is_none_or(|options| options.prefix.is_none())
         })
-        .collect::<anyhow::Result<Vec<_>>>()?;
+        .cloned()
+        .collect::<Vec<_>>();
+    validation::validate_relative_base(&base, &unprefixed).context(ExitError::InvalidArguments)?;
+    Ok(Some((base, repositories)))
+}
 
-    let temp_path = merge_sort_config.as_ref().map(|c| c.tmp_location.clone());
+fn build_gource_log_config(
+    args: &ClapArguments,
+    aliases: HashMap<String, String>,
+    alias_patterns: Vec<AliasPattern>,
+    header: Vec<String>,
+    merge_sort_config: Option<MergeSortConfig>,
+) -> GourceLogConfig {
+    let expand = |path: &String| PathBuf::from(&*shellexpand::tilde(path));
+    GourceLogConfig {
+        output_file: args.output.clone(),
+        aliases,
+        alias_patterns,
+        merge_sort_config,
+        avatar_dir: args.avatar_dir.as_ref().map(expand),
+        checkpoint_every: args.checkpoint_every,
+        resume: args.resume,
+        header,
+        split_by_author: args.split_by_author.as_ref().map(expand),
+        archive: args.archive.as_ref().map(expand),
+        json_output: args.json_output.as_ref().map(expand),
+        debug_dates: args.debug_dates,
+        case_insensitive_authors: args.case_insensitive_authors,
+        format: args.format,
+        compress: args.compress,
+        field_order: args.field_order.clone(),
+        color_by: args.color_by,
+        max_events_per_dir: args.max_events_per_dir,
+        progress_count: !args.no_progress_count,
+        dir_level: args.dir_level,
+        stats: args.stats.as_ref().map(|path| StatsConfig {
+            path: expand(path),
+            format: args.stats_format,
+        }),
+        activity: args.activity_csv.as_ref().map(|path| ActivityConfig {
+            path: expand(path),
+            bucket: args.activity_bucket,
+        }),
+        flatten_single_child_dirs: args.flatten_single_child_dirs,
+    }
+}
 
-    // Do the final sort and write out the log file
-    write_gource_log(
-        logs.into_iter().flatten().collect(),
-        &multi,
-        GourceLogConfig {
-            output_file: args.output,
-            aliases,
-            merge_sort_config,
+fn build_git_log_config(
+    args: &ClapArguments,
+    aliases: &HashMap<String, String>,
+    job_repositories: Option<&JobRepositories>,
+) -> anyhow::Result<GitLogConfig> {
+    Ok(GitLogConfig {
+        max_changeset_size: args.max_changeset_size,
+        path_filter: validation::validate_path_filters(
+            args.sensible_defaults,
+            &args.sensible_defaults_skip,
+            &args.path_include,
+            &args.path_exclude,
+        )?,
+        only_types: args.only_types.clone(),
+        repo_concurrency: args.repo_concurrency,
+        skip_mode_only_changes: args.skip_mode_only_changes,
+        root_commit_mode: args.root_commit_mode,
+        backend: args.backend,
+        group_by_email_domain: args.group_by_email_domain,
+        author_email: args.author_email,
+        skip_lfs: args.skip_lfs,
+        emit_changeset_size: args.emit_changeset_size,
+        skip_recent: args.skip_recent.unwrap_or_default(),
+        all_refs: args.all_refs,
+        first_parent: args.first_parent,
+        since: args.since,
+        until: args.until,
+        sequential: args.sequential,
+        progress_count: !args.no_progress_count,
+        emit_deletes_for_final_state: args.emit_deletes_for_final_state,
+        emit_repo_markers: args.emit_repo_markers,
+        use_mailmap: !args.no_mailmap,
+        co_authors: !args.no_co_authors,
+        copy_as_added: args.copy_as_added,
+        sample_commits: args.sample_commits.unwrap_or(NonZeroUsize::MIN),
+        strict: args.strict,
+        preserve_commit_order: args.preserve_commit_order,
+        absolute_paths: args.absolute_paths,
+        prefix_repo_name: args.prefix_repo_name,
+        keep_empty_commits: args.keep_empty_commits,
+        rename_emit_delete_only: args.rename_emit_delete_only,
+        find_renames: args.detect_renames,
+        identity: if args.committer {
+            Identity::Committer
+        } else {
+            args.identity
         },
-    )?;
+        date_source: args.date_source,
+        merge_attribution: args.merge_attribution,
+        explain_filters: args.explain_filters,
+        excluded_commits: validation::validate_commit_ids(
+            &args.exclude_commit,
+            args.exclude_commits_file
+                .as_ref()
+                .map(|path| PathBuf::from(&*shellexpand::tilde(path)))
+                .as_deref(),
+        )?,
+        author_filter: AuthorFilter::new(
+            &args.author,
+            &args.exclude_author,
+            args.author_ignore_case,
+        ),
+        no_merges: args.no_merges,
+        merges_only: args.merges_only,
+        keep_going: args.keep_going,
+        snapshot_ref: args.snapshot_ref.clone(),
+        start_point: match (&args.branch, &args.until_commit) {
+            (Some(branch), _) => StartPoint::Branch(branch.clone()),
+            (None, Some(sha)) => StartPoint::Commit(sha.clone()),
+            (None, None) if args.default_branch => StartPoint::DefaultBranch,
+            (None, None) => StartPoint::Head,
+        },
+        since_commit: args.since_commit.clone(),
+        dedupe_within_commit: args.dedupe_within_commit,
+        case_insensitive_authors: args.case_insensitive_authors,
+        aliases: aliases.clone(),
+        alias_patterns: validation::validate_alias_patterns(
+            &args.alias_regex,
+            args.case_insensitive_authors,
+        )?,
+        owner_map: args
+            .owner_map
+            .as_ref()
+            .map(|path| validation::validate_owner_map(Path::new(&*shellexpand::tilde(path))))
+            .transpose()?,
+        repository_options: job_repositories
+            .map(|job| job.options.clone())
+            .unwrap_or_default(),
+    })
+}
 
-    // Cleanup if needed
-    let Some(path) = temp_path else { return Ok(()) };
-    let temp_file = path.join(TEMPORARY_LOG_FILENAME);
-    if !temp_file.exists() {
+/// Describe how the log was generated, for the comment lines at the top of the output.
+/// There's only a header with --header-comment, so the default output is reproducible
+fn build_header(args: &ClapArguments, repository_count: Option<usize>) -> Vec<String> {
+    let Some(comment) = args.header_comment.as_ref().filter(|_| !args.no_header) else {
+        return Vec::new();
+    };
+
+    let generated_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let source = repository_count.map_or_else(
+        || "JSON records".to_string(),
+        |count| format!("{count} repositories"),
+    );
+    let mut header = vec![
+        format!(
+            "Generated by rource {} at {generated_at} (unix time) from {source}",
+            env!("CARGO_PKG_VERSION")
+        ),
+        format!(
+            "Command: {}",
+            escape_line_breaks(std::env::args().collect::<Vec<_>>().join(" "))
+        ),
+    ];
+    header.extend(comment.lines().map(ToString::to_string));
+    header
+}
+
+/// Read the logs into the store, with --finalize they were all read by earlier --append-temp runs
+fn read_logs(
+    args: &ClapArguments,
+    repositories: Option<&(PathBuf, Vec<PathBuf>)>,
+    store: &LogStore,
+    multi: &Progress,
+    config: &GitLogConfig,
+) -> anyhow::Result<()> {
+    if args.finalize.is_some() {
         return Ok(());
     }
-    // Remove the temporary file
-    fs::remove_file(temp_file)?;
+    if let Some((root, repositories)) = repositories {
+        return git_stuff::read_repositories(root, repositories, store, multi, config);
+    }
+    let json_file = args
+        .from_json
+        .as_deref()
+        .ok_or_else(|| anyhow!("Either --path or --from-json must be provided"))?;
+    crate::serde::read_json_logs(Path::new(&*shellexpand::tilde(json_file)), config, store)
+}
+
+/// The merge sort is always used with --append-temp and --finalize, which name its temporary file
+fn build_merge_sort_config(args: &ClapArguments) -> anyhow::Result<Option<MergeSortConfig>> {
+    let accumulated = args.append_temp.as_ref().or(args.finalize.as_ref());
+    if !(args.use_merge_sort || args.auto_merge_sort || accumulated.is_some()) {
+        return Ok(None);
+    }
+    let temp_file = accumulated.map(|path| PathBuf::from(&*shellexpand::tilde(path)));
+    if let (Some(_), Some(path)) = (&args.finalize, &temp_file) {
+        if !path.exists() {
+            return Err(anyhow!(
+                "Nothing to finalize, {} does not exist",
+                path.display()
+            ))
+            .context(ExitError::InvalidArguments);
+        }
+    }
+    let mut config = MergeSortConfig::new(args.sort_chunk_size, args.temp_file_location.clone())?;
+    match temp_file {
+        Some(path) => config.temp_file = path,
+        // The temporary file is opened for appending, records left by a run that crashed would end up in this log
+        None if config.temp_file.exists() => {
+            warn!(
+                "Removing {} left over from a previous run",
+                config.temp_file.display()
+            );
+            fs::remove_file(&config.temp_file)
+                .context("Failed to remove the previous run's temporary file")?;
+        }
+        None => {}
+    }
+    config.max_temp_files = args.max_temp_files;
+    Ok(Some(config))
+}
+
+/// Finish an --append-temp run, the records stay in the temporary file for --finalize to sort
+fn finish_append(
+    store: LogStore,
+    path: &str,
+    config: &MergeSortConfig,
+    aliases: &HashMap<String, String>,
+    case_insensitive_authors: bool,
+) -> anyhow::Result<()> {
+    store.finish()?;
+    warnings::log_summary();
+    identities::log_summary(aliases, case_insensitive_authors);
+    info!("Added the logs to {path}, run rource --finalize {path} once every repository has been read");
+    remove_temporary_files(&config.tmp_location)
+}
+
+/// Decide where the logs go while reading the repositories
+fn build_log_store(
+    args: &ClapArguments,
+    merge_sort_config: Option<&MergeSortConfig>,
+) -> anyhow::Result<LogStore> {
+    let Some(config) = merge_sort_config else {
+        return Ok(LogStore::in_memory());
+    };
+    let temp_file = config.temp_file.clone();
+    if args.auto_merge_sort && args.append_temp.is_none() && args.finalize.is_none() {
+        let limit = args.memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT_MB);
+        Ok(LogStore::spill_to_disk(
+            temp_file,
+            usize::try_from(limit * 1024 * 1024)?,
+        ))
+    } else {
+        LogStore::on_disk(&temp_file)
+    }
+}
+
+fn remove_temporary_files(path: &Path) -> anyhow::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    // Remove the temporary file, with --auto-merge-sort it may never have been created
+    let temp_file = path.join(TEMPORARY_LOG_FILENAME);
+    if temp_file.exists() {
+        fs::remove_file(temp_file)?;
+    }
 
     // Hopefully that the last file in the directory
     if path.read_dir()?.next().is_none() {
@@ -151,6 +694,62 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Open the generated log in Gource, or tell the user how to if we can't find it
+fn preview_in_gource(output_file: &str, gource_args: Option<&str>) -> anyhow::Result<()> {
+    let extra_args = gource_args.unwrap_or_default().split_whitespace();
+    let mut command = Command::new("gource");
+    command
+        .args(["--log-format", "custom"])
+        .args(extra_args)
+        .arg(output_file);
+
+    match command.status() {
+        Ok(status) if !status.success() => {
+            warn!("Gource exited with {status}");
+            Ok(())
+        }
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            warn!(
+                "Could not find gource on your PATH, to preview the log run: gource --log-format custom {} {output_file}",
+                gource_args.unwrap_or_default()
+            );
+            Ok(())
+        }
+        Err(e) => Err(e).context("Failed to start gource"),
+    }
+}
+
+/// Report progress to a socket instead of drawing bars in the terminal
+#[cfg(unix)]
+fn progress_socket_reporter(progress: &Progress, path: &str) -> anyhow::Result<ProgressReporter> {
+    let mut writer =
+        progress::SocketProgressWriter::connect(Path::new(&*shellexpand::tilde(path)))?;
+    progress.hide();
+    Ok(progress.spawn_reporter(
+        Duration::from_millis(PROGRESS_SOCKET_INTERVAL_MS),
+        move |s| {
+            writer.send(s);
+        },
+    ))
+}
+
+/// Write a line of overall progress to stderr every so often instead of drawing bars,
+/// the percentage never goes down even when a new phase starts
+fn progress_interval_reporter(progress: &Progress, seconds: NonZeroU64) -> ProgressReporter {
+    progress.hide();
+    let mut highest = 0;
+    progress.spawn_reporter(Duration::from_secs(seconds.get()), move |s| {
+        highest = highest.max(progress::overall_percent(s));
+        let _ = writeln!(io::stderr().lock(), "{highest}% complete");
+    })
+}
+
+#[cfg(not(unix))]
+fn progress_socket_reporter(_progress: &Progress, _path: &str) -> anyhow::Result<ProgressReporter> {
+    anyhow::bail!("--progress-socket is only supported on Unix platforms")
+}
+
 #[cfg(unix)]
 fn reset_pipe() {
     sigpipe::reset();
@@ -161,94 +760,493 @@ fn reset_pipe() {}
 
 pub struct LogSource {
     pub source: Box<dyn Iterator<Item = GourceLogFormat>>,
-    pub size_hint: u64,
+    /// `None` if we don't know how many records there are
+    pub size_hint: Option<u64>,
 }
 
 /// Write out the changes we've accumulated to the target
 fn write_gource_log(
     mut logs: Vec<GourceLogFormat>,
-    progress_bar: &MultiProgress,
-    config: GourceLogConfig,
+    progress_bar: &Progress,
+    config: &GourceLogConfig,
 ) -> anyhow::Result<()> {
     // Setup the progress bar
-    let merge_progress = progress_bar.add(ProgressBar::new_spinner());
-    merge_progress.set_style(
-        ProgressStyle::with_template(DEFAULT_SPINNER_STYLE)?.tick_chars(DEFAULT_SPINNER_TICK_STYLE),
-    );
+    let merge_progress = progress_bar.add_spinner();
     merge_progress.set_prefix("Generating output");
-    merge_progress.enable_steady_tick(Duration::from_millis(100));
     merge_progress.set_message("Merge and Sort");
 
     // Do we need to do a merge sort?
-    let source = if let Some(ms_config) = config.merge_sort_config {
-        let mut reader = DiskLogReader::new(
-            &ms_config.tmp_location.join(TEMPORARY_LOG_FILENAME),
-            progress_bar,
-        )?;
-        let records = reader.record_count()?;
+    let source = if let Some(ms_config) = &config.merge_sort_config {
+        let mut reader = DiskLogReader::new(&ms_config.temp_file, progress_bar)?;
+        let records = if config.progress_count || ms_config.max_temp_files.is_some() {
+            Some(reader.record_count()?)
+        } else {
+            None
+        };
 
-        let sorter: ExternalSorter<GourceLogFormat, io::Error, MemoryLimitedBufferBuilder> =
-            ExternalSorterBuilder::new()
-                .with_tmp_dir(Path::new("./"))
-                .with_buffer(MemoryLimitedBufferBuilder::new(
-                    ms_config.chunk_size * 1024 * 1024,
-                ))
-                .build()?;
+        // Flattening needs to know every directory first, which takes an extra pass over the file
+        let tree = if config.flatten_single_child_dirs {
+            let mut tree = DirectoryTree::default();
+            for log in DiskLogReader::new(&ms_config.temp_file, progress_bar)? {
+                tree.add(&log?.file);
+            }
+            Some(tree)
+        } else {
+            None
+        };
+        let reader = reader.map(move |log| {
+            log.map(|mut log| {
+                if let Some(tree) = &tree {
+                    log.file = tree.flatten(&log.file);
+                }
+                log
+            })
+        });
 
+        // The sorter merges every chunk in a single pass, so the only way to have fewer files is bigger chunks
+        let source = match (ms_config.max_temp_files, records) {
+            (Some(max), Some(records)) => {
+                let chunk_records = usize::try_from(records)?.div_ceil(max.get()).max(1);
+                merge_sort(reader, LimitedBufferBuilder::new(chunk_records, false))?
+            }
+            _ => merge_sort(
+                reader,
+                MemoryLimitedBufferBuilder::new(ms_config.chunk_size * 1024 * 1024),
+            )?,
+        };
         LogSource {
-            size_hint: records,
-            source: Box::new(sorter.sort(reader)?.flatten()),
+            size_hint: records.filter(|_| config.progress_count),
+            source,
         }
     } else {
-        // Sort in memory
-        logs.sort_unstable_by_key(|log| log.timestamp);
+        if config.flatten_single_child_dirs {
+            let mut tree = DirectoryTree::default();
+            for log in &logs {
+                tree.add(&log.file);
+            }
+            for log in &mut logs {
+                log.file = tree.flatten(&log.file);
+            }
+        }
+        // Sort in memory, using the full ordering so the output matches the merge sort.
+        // A single repository read oldest first is often in order already, checking is much cheaper than sorting
+        if logs.is_sorted() {
+            debug!("Logs are already sorted, skipping the sort");
+        } else {
+            logs.sort_unstable();
+        }
         LogSource {
-            size_hint: logs.len() as u64,
+            size_hint: Some(logs.len() as u64),
             source: Box::new(logs.into_iter()),
         }
     };
 
     merge_progress.set_message("Gourcification");
-    write_to_output(source, config.output_file, &config.aliases, progress_bar)?;
+    write_to_output(source, config, progress_bar)?;
     merge_progress.finish_with_message("Done");
 
     Ok(())
 }
 
+/// Sort the records from the temporary file, writing chunks of them to disk as `buffer` fills up
+fn merge_sort<B>(
+    reader: impl Iterator<Item = io::Result<GourceLogFormat>> + 'static,
+    buffer: B,
+) -> anyhow::Result<Box<dyn Iterator<Item = GourceLogFormat>>>
+where
+    B: ChunkBufferBuilder<GourceLogFormat> + 'static,
+{
+    let sorter: ExternalSorter<GourceLogFormat, io::Error, B> = ExternalSorterBuilder::new()
+        .with_tmp_dir(Path::new("./"))
+        .with_buffer(buffer)
+        .build()?;
+    Ok(Box::new(sorter.sort(reader)?.flatten()))
+}
+
 fn write_to_output(
     source: LogSource,
-    output_file: Option<String>,
-    aliases: &HashMap<String, String>,
-    multi_progress: &MultiProgress,
+    config: &GourceLogConfig,
+    multi_progress: &Progress,
 ) -> anyhow::Result<()> {
-    let progress_bar = multi_progress.add(
-        ProgressBar::new(source.size_hint)
-            .with_style(ProgressStyle::with_template(DEFAULT_PROGRESS_STYLE)?),
-    );
+    let progress_bar = multi_progress.add_counter(source.size_hint);
 
     progress_bar.set_prefix("Writing Gource Log");
 
-    // Set the output stream
-    let output_stream: Box<dyn Write> = match output_file {
-        Some(path) => Box::new(fs::File::create(path)?),
-        None => Box::new(io::stdout()),
+    let checkpoint_file = config
+        .output_file
+        .as_ref()
+        .filter(|_| config.checkpoint_every.is_some())
+        .map(|output| checkpoint_path(Path::new(output)));
+    let resume_from = match &checkpoint_file {
+        Some(path) if config.resume => Checkpoint::read(path)?,
+        _ => None,
     };
+    let mut writer = OutputWriter::new(config, resume_from.as_ref().map(|c| c.bytes))?;
 
-    // Use CSV to write the logs using Serde
-    let mut writer = csv::WriterBuilder::new()
-        .has_headers(false)
-        .delimiter(b'|')
-        .quote_style(QuoteStyle::Necessary)
-        .from_writer(output_stream);
+    // Keep track of who appears in the log if we need to know about their avatars
+    let mut usernames = BTreeSet::new();
+    let mut stats = config.stats.as_ref().map(|_| Stats::default());
+    let mut activity = config
+        .activity
+        .as_ref()
+        .map(ActivityWriter::new)
+        .transpose()?;
+    let mut records = 0_u64;
+    let mut author_names = AuthorNames::new(
+        &config.aliases,
+        &config.alias_patterns,
+        config.case_insensitive_authors,
+    );
+    let mut directory_cap = config
+        .max_events_per_dir
+        .map(|max| DirectoryCap::new(max, config.dir_level));
 
     for mut log in source.source {
-        // Apply any aliases
         progress_bar.inc(1);
-        if let Some(alias) = aliases.get(&log.username) {
-            log.username = alias.to_string();
+        if let Some(cap) = &mut directory_cap {
+            if !cap.allows(&log.file) {
+                continue;
+            }
+        }
+        author_names.resolve(&mut log.username);
+        if let Some(color) = config.color_by.color(&log) {
+            log.color = Some(color);
         }
-        writer.serialize(log)?;
+        if config.avatar_dir.is_some() && !usernames.contains(&log.username) {
+            usernames.insert(log.username.clone());
+        }
+        if let Some(stats) = &mut stats {
+            stats.record(&log);
+        }
+        if let Some(activity) = &mut activity {
+            activity.record(&log)?;
+        }
+        records += 1;
+        // Everything else still sees the records the checkpoint covers, they're already in the output
+        if let Some(checkpoint) = resume_from.as_ref().filter(|c| records <= c.records) {
+            if records == checkpoint.records && log.timestamp != checkpoint.timestamp {
+                bail!(
+                    "The logs don't match the checkpoint, were the options or repositories changed? \
+                    Remove --resume to write the output from the start"
+                );
+            }
+            continue;
+        }
+        writer.write(&log)?;
+
+        if let (Some(every), Some(path), Some(output)) = (
+            config.checkpoint_every,
+            &checkpoint_file,
+            &config.output_file,
+        ) {
+            if records.is_multiple_of(every.get()) {
+                writer.flush()?;
+                Checkpoint {
+                    records,
+                    timestamp: log.timestamp,
+                    bytes: fs::metadata(partial_output_path(Path::new(output)))?.len(),
+                }
+                .write(path)?;
+            }
+        }
+    }
+    if let Some(checkpoint) = resume_from.filter(|c| records < c.records) {
+        bail!(
+            "Only {records} records to write but the checkpoint has {}, were the options or repositories changed? \
+            Remove --resume to write the output from the start",
+            checkpoint.records
+        );
     }
     progress_bar.finish_with_message("Done");
-    writer.flush().context("Failed to write output")
+    writer.finish()?;
+
+    // The output is complete, so there's nothing left to recover
+    if let Some(path) = checkpoint_file.filter(|path| path.exists()) {
+        fs::remove_file(path)?;
+    }
+
+    if let Some(avatar_dir) = &config.avatar_dir {
+        write_avatar_manifest(avatar_dir, &usernames)?;
+    }
+    if let (Some(stats), Some(stats_config)) = (stats, &config.stats) {
+        stats.write(stats_config)?;
+    }
+    if let Some(activity) = activity {
+        activity.finish()?;
+    }
+    Ok(())
+}
+
+/// The checkpoint sits next to the partial output it describes, `<OUTPUT>.partial.checkpoint`
+fn checkpoint_path(output: &Path) -> PathBuf {
+    let mut path = partial_output_path(output).into_os_string();
+    path.push(CHECKPOINT_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// How far the write phase got, everything up to this point has already been flushed to the partial output
+#[derive(Debug, PartialEq, Eq)]
+struct Checkpoint {
+    /// How many records had been written
+    records: u64,
+    /// The timestamp of the last of them
+    timestamp: i64,
+    /// The size of the partial output after them
+    bytes: u64,
+}
+
+impl Checkpoint {
+    /// Saved as `<records>|<timestamp>|<bytes>`
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(
+            path,
+            format!("{}|{}|{}\n", self.records, self.timestamp, self.bytes),
+        )
+        .context("Failed to write checkpoint")
+    }
+
+    /// The checkpoint an interrupted run left, if there is one
+    fn read(path: &Path) -> anyhow::Result<Option<Self>> {
+        if !path.exists() {
+            info!(
+                "No checkpoint at {}, writing the output from the start",
+                path.display()
+            );
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)
+            .context(format!("Failed to read checkpoint {}", path.display()))?;
+        let invalid = || anyhow!("Invalid checkpoint {}", path.display());
+        let mut fields = contents.trim().split('|');
+        let mut next = || fields.next().ok_or_else(invalid);
+        let checkpoint = Self {
+            records: next()?.parse().map_err(|_| invalid())?,
+            timestamp: next()?.parse().map_err(|_| invalid())?,
+            bytes: next()?.parse().map_err(|_| invalid())?,
+        };
+        Ok(Some(checkpoint))
+    }
+}
+
+/// Create the avatar directory for Gource's --user-image-dir and list the images it should contain,
+/// Gource looks for an image named exactly after the username, e.g. 'Some User.png'
+fn write_avatar_manifest(avatar_dir: &Path, usernames: &BTreeSet<String>) -> anyhow::Result<()> {
+    fs::create_dir_all(avatar_dir).context("Failed to create avatar directory")?;
+
+    let mut missing = Vec::new();
+    for username in usernames {
+        if username.contains(['/', '\\']) {
+            warn!("Username {username:?} can't be used as an avatar filename");
+            continue;
+        }
+        let exists = AVATAR_EXTENSIONS
+            .iter()
+            .any(|ext| avatar_dir.join(format!("{username}.{ext}")).exists());
+        if !exists {
+            missing.push(format!("{username}.png"));
+        }
+    }
+
+    let manifest = avatar_dir.join(AVATAR_MANIFEST_FILENAME);
+    let mut writer = io::BufWriter::new(fs::File::create(&manifest)?);
+    for filename in &missing {
+        writeln!(writer, "{filename}")?;
+    }
+    writer.flush()?;
+
+    info!(
+        "{} of {} users are missing an avatar, see {}",
+        missing.len(),
+        usernames.len(),
+        manifest.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::ProgressStyles;
+    use crate::structs::GourceActionType;
+    use crate::test_support::{arguments, gource_config};
+
+    fn logs() -> Vec<GourceLogFormat> {
+        (0..5)
+            .map(|i| GourceLogFormat {
+                timestamp: 100 + i,
+                username: "Ann".to_string(),
+                r#type: GourceActionType::A,
+                file: format!("{i}.txt"),
+                changeset_size: None,
+                sequence: None,
+                color: None,
+            })
+            .collect()
+    }
+
+    fn hidden_progress() -> anyhow::Result<Progress> {
+        let progress = Progress::new(ProgressStyles::new(None, None)?);
+        progress.hide();
+        Ok(progress)
+    }
+
+    fn write(logs: Vec<GourceLogFormat>, config: &GourceLogConfig) -> anyhow::Result<()> {
+        let progress = hidden_progress()?;
+        let source = LogSource {
+            size_hint: None,
+            source: Box::new(logs.into_iter()),
+        };
+        write_to_output(source, config, &progress)
+    }
+
+    #[test]
+    fn in_memory_and_merge_sorts_give_the_same_output() -> anyhow::Result<()> {
+        // Plenty of records share a timestamp, so the order only comes from the other fields
+        let mut logs = Vec::new();
+        for i in 0..200_u32 {
+            logs.push(GourceLogFormat {
+                timestamp: 100 + i64::from(i % 7),
+                username: ["Ann", "Bob", "Cat"][(i % 3) as usize].to_string(),
+                r#type: [
+                    GourceActionType::A,
+                    GourceActionType::M,
+                    GourceActionType::D,
+                ][(i % 5 % 3) as usize],
+                file: format!("dir{}/{}.txt", i % 4, i % 11),
+                changeset_size: None,
+                sequence: None,
+                color: None,
+            });
+        }
+        // Reverse them so neither sort starts from sorted input
+        logs.reverse();
+
+        let dir = tempfile::tempdir()?;
+        let in_memory = dir.path().join("in-memory.log");
+        let config = gource_config(dir.path(), &["--output", &in_memory.to_string_lossy()])?;
+        write_gource_log(logs.clone(), &hidden_progress()?, &config)?;
+
+        let merged = dir.path().join("merged.log");
+        let mut config = gource_config(dir.path(), &["--output", &merged.to_string_lossy()])?;
+        let temp_location = dir.path().join("temp");
+        let mut merge_sort_config =
+            MergeSortConfig::new(None, Some(temp_location.to_string_lossy().into_owned()))?;
+        // Several chunks, so they're merged as well as sorted
+        merge_sort_config.max_temp_files = NonZeroUsize::new(4);
+        let store = LogStore::on_disk(&merge_sort_config.temp_file)?;
+        store.store(logs)?;
+        assert!(store.finish()?.is_empty());
+        config.merge_sort_config = Some(merge_sort_config);
+        write_gource_log(Vec::new(), &hidden_progress()?, &config)?;
+
+        assert_eq!(fs::read(&in_memory)?, fs::read(&merged)?);
+        Ok(())
+    }
+
+    #[test]
+    fn leftover_temporary_files_are_removed() -> anyhow::Result<()> {
+        // A run that crashed left a record in the temporary directory
+        let dir = tempfile::tempdir()?;
+        let location = dir.path().join("rource-temp-crashed");
+        let stale = location.join(TEMPORARY_LOG_FILENAME);
+        fs::create_dir(&location)?;
+        let store = LogStore::on_disk(&stale)?;
+        store.store(logs())?;
+        store.finish()?;
+        assert!(fs::metadata(&stale)?.len() > 0);
+
+        let args = arguments(
+            dir.path(),
+            &[
+                "--use-merge-sort",
+                "--temp-file-location",
+                &location.to_string_lossy(),
+            ],
+        )?;
+        let config = build_merge_sort_config(&args)?.ok_or_else(|| anyhow!("No merge sort"))?;
+        assert_eq!(config.temp_file, stale);
+        assert!(!stale.exists());
+
+        // So the new run's records aren't added to the old ones
+        let store = LogStore::on_disk(&config.temp_file)?;
+        store.store(logs()[..1].to_vec())?;
+        store.finish()?;
+        let mut reader = DiskLogReader::new(&config.temp_file, &hidden_progress()?)?;
+        assert_eq!(reader.record_count()?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoints_are_read_back() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = checkpoint_path(&dir.path().join("gource.log"));
+        assert!(path.ends_with("gource.log.partial.checkpoint"));
+        assert_eq!(Checkpoint::read(&path)?, None);
+
+        let checkpoint = Checkpoint {
+            records: 2000,
+            timestamp: 1_700_000_000,
+            bytes: 65_536,
+        };
+        checkpoint.write(&path)?;
+        assert_eq!(Checkpoint::read(&path)?, Some(checkpoint));
+        Ok(())
+    }
+
+    #[test]
+    fn resume_carries_on_from_the_checkpoint() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("gource.log");
+        let output_arg = output.to_string_lossy();
+        let args = ["--output", &output_arg, "--checkpoint-every", "2"];
+        write(logs(), &gource_config(dir.path(), &args)?)?;
+        let expected = fs::read_to_string(&output)?;
+        assert!(!checkpoint_path(&output).exists());
+        fs::remove_file(&output)?;
+
+        // A run that stopped after its first checkpoint, part way through writing another record
+        let checkpointed = expected.split_inclusive('\n').take(2).collect::<String>();
+        fs::write(
+            partial_output_path(&output),
+            format!("{checkpointed}102|An"),
+        )?;
+        Checkpoint {
+            records: 2,
+            timestamp: 101,
+            bytes: checkpointed.len() as u64,
+        }
+        .write(&checkpoint_path(&output))?;
+
+        let resume = gource_config(dir.path(), &[&args[..], &["--resume"]].concat())?;
+        write(logs(), &resume)?;
+        assert_eq!(fs::read_to_string(&output)?, expected);
+        assert!(!checkpoint_path(&output).exists());
+
+        // Different logs to the ones the checkpoint was written for
+        fs::write(partial_output_path(&output), &checkpointed)?;
+        Checkpoint {
+            records: 2,
+            timestamp: 900,
+            bytes: checkpointed.len() as u64,
+        }
+        .write(&checkpoint_path(&output))?;
+        assert!(write(logs(), &resume).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn header_is_only_written_with_a_header_comment() -> anyhow::Result<()> {
+        let path = Path::new(".");
+        assert!(build_header(&arguments(path, &[])?, Some(1)).is_empty());
+
+        let header = build_header(
+            &arguments(path, &["--header-comment", "Nightly\nbuild"])?,
+            Some(2),
+        );
+        assert_eq!(header.len(), 4);
+        assert!(header[0].ends_with("from 2 repositories"));
+        assert!(header[1].starts_with("Command: "));
+        assert_eq!(header[2..], ["Nightly", "build"]);
+        Ok(())
+    }
 }
@@ -0,0 +1,793 @@
+use crate::consts::{MAX_OPEN_SPLIT_FILES, PARTIAL_OUTPUT_SUFFIX, SPLIT_LOG_EXTENSION};
+use crate::stats::file_extension;
+use crate::structs::{GourceActionType, GourceLogConfig, GourceLogFormat};
+use crate::validation::format_date;
+use anyhow::Context;
+use clap::ValueEnum;
+use csv::QuoteStyle;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::warn;
+use serde::ser::SerializeTuple;
+use serde::{Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// A column of the Gource log, for --field-order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogField {
+    Timestamp,
+    Username,
+    Type,
+    File,
+}
+
+impl LogField {
+    /// The order Gource expects
+    pub const GOURCE_ORDER: [Self; 4] = [Self::Timestamp, Self::Username, Self::Type, Self::File];
+
+    /// The name of the field in `GourceLogFormat`
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Timestamp => "timestamp",
+            Self::Username => "username",
+            Self::Type => "type",
+            Self::File => "file",
+        }
+    }
+}
+
+/// What the log is written as, for --format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Gource's pipe separated custom log format
+    Gource,
+    /// One JSON object per line, the same records as --json-output
+    Json,
+}
+
+/// How the output is compressed, for --compress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputCompression {
+    None,
+    Gzip,
+}
+
+impl OutputCompression {
+    /// The --compress setting if there is one, otherwise gzip when the output path ends in `.gz`
+    pub fn for_output(compress: Option<Self>, path: Option<&Path>) -> Self {
+        compress.unwrap_or_else(|| {
+            if path.is_some_and(|path| path.extension().is_some_and(|ext| ext == "gz")) {
+                Self::Gzip
+            } else {
+                Self::None
+            }
+        })
+    }
+}
+
+/// What the colour column is based on, for --color-by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorBy {
+    /// Leave the colour column out
+    None,
+    /// A colour for each author
+    User,
+    /// A colour for each file extension
+    Extension,
+}
+
+impl ColorBy {
+    /// The colour for a record, as Gource expects it in hex without a leading '#'
+    pub fn color(self, log: &GourceLogFormat) -> Option<String> {
+        match self {
+            Self::None => None,
+            Self::User => Some(stable_color(&log.username)),
+            Self::Extension => Some(stable_color(&file_extension(&log.file))),
+        }
+    }
+}
+
+/// A bright colour picked by hashing the name. FNV-1a rather than the standard library's hasher,
+/// which isn't guaranteed to give the same result between Rust versions
+fn stable_color(name: &str) -> String {
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    // Spread the hue around the colour wheel, keeping the saturation and brightness high enough to stand out
+    let hue = hash % 360;
+    let (high, low) = (242_u64, 85_u64);
+    let rising = low + (high - low) * (hue % 60) / 60;
+    let falling = high - (high - low) * (hue % 60) / 60;
+    let (red, green, blue) = match hue / 60 {
+        0 => (high, rising, low),
+        1 => (falling, high, low),
+        2 => (low, high, rising),
+        3 => (low, falling, high),
+        4 => (rising, low, high),
+        _ => (high, low, falling),
+    };
+    format!("{red:02X}{green:02X}{blue:02X}")
+}
+
+/// Where the finished log is written, every record goes to each of the sinks
+pub struct OutputWriter {
+    sinks: Vec<Sink>,
+    /// Add the date to every record, for --debug-dates
+    debug_dates: bool,
+    field_order: Vec<LogField>,
+}
+
+enum Sink {
+    /// A single Gource log, to a file or stdout
+    Single(Box<csv::Writer<OutputStream>>),
+    /// One Gource log per author in a directory
+    ByAuthor(AuthorSplitWriter),
+    /// One Gource log per author in a tar.gz archive, for --archive, spooled to disk until the end
+    Archive(Box<AuthorArchiveWriter>),
+    /// Newline delimited JSON records, for --json-output or --format json
+    Json(io::BufWriter<OutputStream>),
+}
+
+impl OutputWriter {
+    /// Open every output the config asks for. With `resume_at` the partial output an interrupted run left
+    /// is carried on from that many bytes in, instead of starting a new one
+    pub fn new(config: &GourceLogConfig, resume_at: Option<u64>) -> anyhow::Result<Self> {
+        let mut sinks = Vec::with_capacity(2);
+        if let (Some(directory), Some(archive)) = (&config.split_by_author, &config.archive) {
+            sinks.push(Sink::Archive(Box::new(AuthorArchiveWriter::new(
+                directory, archive, config,
+            )?)));
+        } else if let Some(directory) = &config.split_by_author {
+            sinks.push(Sink::ByAuthor(AuthorSplitWriter::new(
+                directory,
+                config.header.clone(),
+                config.debug_dates,
+                config.field_order.clone(),
+            )?));
+        } else {
+            let path = config.output_file.as_ref().map(Path::new);
+            let compression = OutputCompression::for_output(config.compress, path);
+            let mut output_stream = match (path, resume_at) {
+                (Some(path), Some(bytes)) => {
+                    if compression != OutputCompression::None {
+                        anyhow::bail!("Compressed output can't be resumed");
+                    }
+                    OutputStream::resume(path, bytes)?
+                }
+                (Some(path), None) => OutputStream::file(path)?,
+                (None, _) => OutputStream::Stdout(io::stdout()),
+            }
+            .compressed(compression);
+            match config.format {
+                OutputFormat::Gource => {
+                    // A resumed output already has its header
+                    if resume_at.is_none() {
+                        write_header(&mut output_stream, &config.header)?;
+                    }
+                    sinks.push(Sink::Single(Box::new(gource_writer(output_stream))));
+                }
+                // JSON has no comments to put the header in
+                OutputFormat::Json => sinks.push(Sink::Json(io::BufWriter::new(output_stream))),
+            }
+        }
+
+        if let Some(path) = &config.json_output {
+            let output_stream = OutputStream::file(path)?
+                .compressed(OutputCompression::for_output(None, Some(path)));
+            sinks.push(Sink::Json(io::BufWriter::new(output_stream)));
+        }
+        Ok(Self {
+            sinks,
+            debug_dates: config.debug_dates,
+            field_order: config.field_order.clone(),
+        })
+    }
+
+    pub fn write(&mut self, log: &GourceLogFormat) -> anyhow::Result<()> {
+        for sink in &mut self.sinks {
+            match sink {
+                Sink::Single(writer) => {
+                    write_gource_line(writer, log, &self.field_order, self.debug_dates)?;
+                }
+                Sink::ByAuthor(writer) => writer.write(log)?,
+                Sink::Archive(writer) => writer.write(log)?,
+                Sink::Json(writer) => {
+                    serde_json::to_writer(&mut *writer, &JsonLine::new(log, self.debug_dates))?;
+                    writer.write_all(b"\n")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        for sink in &mut self.sinks {
+            match sink {
+                Sink::Single(writer) => writer.flush().context("Failed to write output")?,
+                Sink::ByAuthor(writer) => writer.flush()?,
+                Sink::Archive(writer) => writer.logs.flush()?,
+                Sink::Json(writer) => writer.flush().context("Failed to write JSON output")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush everything and move the output files into place
+    pub fn finish(self) -> anyhow::Result<()> {
+        for sink in self.sinks {
+            match sink {
+                Sink::Single(writer) => writer
+                    .into_inner()
+                    .map_err(|e| anyhow::anyhow!("Failed to write output - {}", e.error()))?
+                    .finish()?,
+                Sink::ByAuthor(mut writer) => writer.flush()?,
+                Sink::Archive(writer) => writer.finish()?,
+                Sink::Json(writer) => writer
+                    .into_inner()
+                    .map_err(|e| anyhow::anyhow!("Failed to write JSON output - {}", e.error()))?
+                    .finish()?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The output file is written next to its final path and renamed once it's complete,
+/// so an interrupted run never leaves a truncated log in place of a previous good one
+pub enum OutputStream {
+    File {
+        file: fs::File,
+        partial: PathBuf,
+        target: PathBuf,
+    },
+    Stdout(io::Stdout),
+    /// Either of the others, gzip compressed
+    Gzip(Box<GzEncoder<Self>>),
+}
+
+impl OutputStream {
+    fn file(target: &Path) -> anyhow::Result<Self> {
+        let partial = partial_output_path(target);
+        let file = fs::File::create(&partial)
+            .context(format!("Failed to create {}", partial.display()))?;
+        Ok(Self::File {
+            file,
+            partial,
+            target: target.to_path_buf(),
+        })
+    }
+
+    /// Carry on with the partial output an interrupted run left, dropping anything after `bytes`
+    fn resume(target: &Path, bytes: u64) -> anyhow::Result<Self> {
+        let partial = partial_output_path(target);
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(&partial)
+            .context(format!("Failed to open {} to resume it", partial.display()))?;
+        if file.metadata()?.len() < bytes {
+            anyhow::bail!(
+                "{} is shorter than its checkpoint, it can't be resumed",
+                partial.display()
+            );
+        }
+        file.set_len(bytes)?;
+        file.seek(io::SeekFrom::End(0))?;
+        Ok(Self::File {
+            file,
+            partial,
+            target: target.to_path_buf(),
+        })
+    }
+
+    fn compressed(self, compression: OutputCompression) -> Self {
+        match compression {
+            OutputCompression::None => self,
+            OutputCompression::Gzip => {
+                Self::Gzip(Box::new(GzEncoder::new(self, Compression::default())))
+            }
+        }
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        // Without its trailer the file can't be decompressed, so the encoder has to finish first
+        if let Self::Gzip(encoder) = self {
+            return encoder
+                .finish()
+                .context("Failed to finish the compressed output")?
+                .finish();
+        }
+        if let Self::File {
+            file,
+            partial,
+            target,
+        } = self
+        {
+            file.sync_all()?;
+            drop(file);
+            fs::rename(&partial, &target).context(format!(
+                "Failed to move {} to {}",
+                partial.display(),
+                target.display()
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for OutputStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::File { file, .. } => file.write(buf),
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::File { file, .. } => file.flush(),
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Where the output is written until it's complete, e.g. `gource.log.partial`
+pub fn partial_output_path(target: &Path) -> PathBuf {
+    let mut partial = target.as_os_str().to_os_string();
+    partial.push(PARTIAL_OUTPUT_SUFFIX);
+    PathBuf::from(partial)
+}
+
+/// Write a record to a Gource log. With --debug-dates its date goes on a comment line before it,
+/// which Gource skips like the header, so the record itself keeps its usual columns
+fn write_gource_line<W: Write>(
+    writer: &mut csv::Writer<W>,
+    log: &GourceLogFormat,
+    fields: &[LogField],
+    debug_dates: bool,
+) -> csv::Result<()> {
+    if debug_dates {
+        writer.write_record([format!("# {}", format_date(log.timestamp))])?;
+    }
+    writer.serialize(GourceLine { log, fields })
+}
+
+/// The four columns of Gource's custom log format, in the order given by --field-order,
+/// followed by the colour column when the record has one
+struct GourceLine<'a> {
+    log: &'a GourceLogFormat,
+    fields: &'a [LogField],
+}
+
+impl Serialize for GourceLine<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let extra = usize::from(self.log.color.is_some());
+        let mut line = serializer.serialize_tuple(self.fields.len() + extra)?;
+        for field in self.fields {
+            match field {
+                LogField::Timestamp => line.serialize_element(&self.log.timestamp)?,
+                LogField::Username => line.serialize_element(&self.log.username)?,
+                LogField::Type => line.serialize_element(&self.log.r#type)?,
+                LogField::File => line.serialize_element(&self.log.file)?,
+            }
+        }
+        if let Some(color) = &self.log.color {
+            line.serialize_element(color)?;
+        }
+        line.end()
+    }
+}
+
+/// A record in the --json-output or `--format json`, the optional fields are only written when they're set.
+/// With --debug-dates it has the date as well as the timestamp
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: i64,
+    username: &'a str,
+    r#type: GourceActionType,
+    file: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changeset_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+}
+
+impl<'a> JsonLine<'a> {
+    fn new(log: &'a GourceLogFormat, debug_dates: bool) -> Self {
+        Self {
+            timestamp: log.timestamp,
+            username: &log.username,
+            r#type: log.r#type,
+            file: &log.file,
+            changeset_size: log.changeset_size,
+            sequence: log.sequence,
+            color: log.color.as_deref(),
+            date: debug_dates.then(|| format_date(log.timestamp)),
+        }
+    }
+}
+
+/// Use CSV to write the logs in Gource's pipe separated format.
+/// Flexible, since the --debug-dates comments are a single column between the records
+fn gource_writer<W: Write>(writer: W) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(b'|')
+        .quote_style(QuoteStyle::Necessary)
+        .from_writer(writer)
+}
+
+/// Gource skips lines it can't parse, so the header is harmless
+fn write_header<W: Write>(writer: &mut W, header: &[String]) -> io::Result<()> {
+    for line in header {
+        writeln!(writer, "# {line}")?;
+    }
+    Ok(())
+}
+
+/// Picks the filename of each author's log
+struct SplitFilenames {
+    directory: PathBuf,
+    filenames: HashMap<String, PathBuf>,
+    /// Every filename given out, in order
+    assigned: Vec<PathBuf>,
+    /// Lowercase, so names that only differ by case don't clash on case-insensitive filesystems
+    used: HashSet<String>,
+}
+
+impl SplitFilenames {
+    fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            filenames: HashMap::new(),
+            assigned: Vec::new(),
+            used: HashSet::new(),
+        }
+    }
+
+    fn get(&self, username: &str) -> Option<&PathBuf> {
+        self.filenames.get(username)
+    }
+
+    /// Give an author their own filename, authors whose names only differ by unsafe characters get a numbered suffix
+    fn assign(&mut self, username: &str) -> PathBuf {
+        let name = safe_filename(username);
+        let mut path = self.directory.join(format!("{name}.{SPLIT_LOG_EXTENSION}"));
+        let mut suffix = 2;
+        while self.used.contains(&path.to_string_lossy().to_lowercase()) {
+            path = self
+                .directory
+                .join(format!("{name}-{suffix}.{SPLIT_LOG_EXTENSION}"));
+            suffix += 1;
+        }
+        self.used.insert(path.to_string_lossy().to_lowercase());
+        self.filenames.insert(username.to_string(), path.clone());
+        self.assigned.push(path.clone());
+        path
+    }
+}
+
+/// Routes each record to a log file named after its author.
+/// Only a limited number of files are kept open, the rest are reopened for appending when needed
+pub struct AuthorSplitWriter {
+    header: Vec<String>,
+    debug_dates: bool,
+    field_order: Vec<LogField>,
+    filenames: SplitFilenames,
+    open: HashMap<String, csv::Writer<io::BufWriter<fs::File>>>,
+}
+
+impl AuthorSplitWriter {
+    fn new(
+        directory: &Path,
+        header: Vec<String>,
+        debug_dates: bool,
+        field_order: Vec<LogField>,
+    ) -> anyhow::Result<Self> {
+        fs::create_dir_all(directory).context("Failed to create --split-by-author directory")?;
+        Ok(Self {
+            header,
+            debug_dates,
+            field_order,
+            filenames: SplitFilenames::new(directory.to_path_buf()),
+            open: HashMap::new(),
+        })
+    }
+
+    fn write(&mut self, log: &GourceLogFormat) -> anyhow::Result<()> {
+        if !self.open.contains_key(&log.username) {
+            if self.open.len() >= MAX_OPEN_SPLIT_FILES {
+                self.flush()?;
+                self.open.clear();
+            }
+            let writer = self.open_file(&log.username)?;
+            self.open.insert(log.username.clone(), writer);
+        }
+        if let Some(writer) = self.open.get_mut(&log.username) {
+            write_gource_line(writer, log, &self.field_order, self.debug_dates)?;
+        }
+        Ok(())
+    }
+
+    /// Create the author's file the first time we see them, after that append to it
+    fn open_file(
+        &mut self,
+        username: &str,
+    ) -> anyhow::Result<csv::Writer<io::BufWriter<fs::File>>> {
+        if let Some(path) = self.filenames.get(username) {
+            let file = fs::OpenOptions::new().append(true).open(path)?;
+            return Ok(gource_writer(io::BufWriter::new(file)));
+        }
+
+        let path = self.filenames.assign(username);
+        let mut file = io::BufWriter::new(
+            fs::File::create(&path).context(format!("Failed to create {}", path.display()))?,
+        );
+        write_header(&mut file, &self.header)?;
+        Ok(gource_writer(file))
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        for writer in self.open.values_mut() {
+            writer.flush().context("Failed to write output")?;
+        }
+        Ok(())
+    }
+
+    /// Flush and close every file, they can be opened again if there's more to write
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        self.open.clear();
+        Ok(())
+    }
+
+    /// The files written so far, in the order the authors were first seen
+    fn paths(&self) -> &[PathBuf] {
+        &self.filenames.assigned
+    }
+}
+
+/// Writes each author's log to a spool file in the system temporary directory, then copies them
+/// into a tar.gz archive at the end. They can't be streamed straight into the archive, a tar entry's size
+/// comes before its contents and the authors' records are interleaved, so no entry is complete until the end.
+/// The entries are named the same as the files --split-by-author would write, under a folder named after its directory
+pub struct AuthorArchiveWriter {
+    archive: PathBuf,
+    folder: PathBuf,
+    /// Dropped before `spool`, so the files are closed before the directory is removed
+    logs: AuthorSplitWriter,
+    /// A new directory of its own, removed when the writer is dropped even if the run fails
+    spool: tempfile::TempDir,
+}
+
+impl AuthorArchiveWriter {
+    fn new(directory: &Path, archive: &Path, config: &GourceLogConfig) -> anyhow::Result<Self> {
+        let spool = tempfile::Builder::new()
+            .prefix("rource-archive-")
+            .tempdir()
+            .context("Failed to create a directory for the --archive logs")?;
+        let logs = AuthorSplitWriter::new(
+            spool.path(),
+            config.header.clone(),
+            config.debug_dates,
+            config.field_order.clone(),
+        )?;
+        Ok(Self {
+            archive: archive.to_path_buf(),
+            folder: directory.file_name().map(PathBuf::from).unwrap_or_default(),
+            spool,
+            logs,
+        })
+    }
+
+    fn write(&mut self, log: &GourceLogFormat) -> anyhow::Result<()> {
+        self.logs.write(log)
+    }
+
+    /// Write the archive, the spool files are removed whether or not it succeeds
+    fn finish(mut self) -> anyhow::Result<()> {
+        let result = self.logs.close().and_then(|()| self.write_archive());
+        let spool = self.spool.path().to_path_buf();
+        if let Err(e) = self.spool.close() {
+            warn!("Failed to remove {}: {e}", spool.display());
+        }
+        result
+    }
+
+    fn write_archive(&self) -> anyhow::Result<()> {
+        let mut archive = tar::Builder::new(GzEncoder::new(
+            OutputStream::file(&self.archive)?,
+            Compression::default(),
+        ));
+        for path in self.logs.paths() {
+            let name = self.folder.join(path.file_name().unwrap_or_default());
+            archive
+                .append_path_with_name(path, &name)
+                .context(format!("Failed to add {} to the archive", name.display()))?;
+        }
+        archive
+            .into_inner()
+            .and_then(GzEncoder::finish)
+            .context(format!("Failed to write {}", self.archive.display()))?
+            .finish()
+    }
+}
+
+/// Replace anything that isn't safe in a filename on every platform
+fn safe_filename(username: &str) -> String {
+    let name = username
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    // Leading dots would hide the file, trailing dots and spaces are dropped by Windows
+    let name = name.trim_start_matches('.').trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::gource_config;
+    use std::io::Read;
+
+    #[test]
+    fn archives_are_spooled_to_disk_then_removed() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let archive = dir.path().join("logs.tar.gz");
+        let config = gource_config(
+            dir.path(),
+            &[
+                "--split-by-author",
+                &dir.path().join("authors").to_string_lossy(),
+                "--archive",
+                &archive.to_string_lossy(),
+            ],
+        )?;
+        let mut writer = AuthorArchiveWriter::new(Path::new("authors"), &archive, &config)?;
+        let spool = writer.spool.path().to_path_buf();
+        writer.write(&log())?;
+        writer.write(&GourceLogFormat {
+            username: "Bob".to_string(),
+            ..log()
+        })?;
+        writer.write(&GourceLogFormat {
+            timestamp: 1_700_000_001,
+            ..log()
+        })?;
+        // The records are on disk, not held until the end
+        writer.logs.flush()?;
+        assert!(fs::metadata(spool.join("Ann.log"))?.len() > 0);
+        writer.finish()?;
+        assert!(!spool.exists());
+
+        let mut entries = Vec::new();
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(fs::File::open(&archive)?));
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            entries.push((entry.path()?.to_string_lossy().into_owned(), contents));
+        }
+        assert_eq!(
+            entries,
+            [
+                (
+                    "authors/Ann.log".to_string(),
+                    "1700000000|Ann|M|src/main.rs\n1700000001|Ann|M|src/main.rs\n".to_string()
+                ),
+                (
+                    "authors/Bob.log".to_string(),
+                    "1700000000|Bob|M|src/main.rs\n".to_string()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    fn log() -> GourceLogFormat {
+        GourceLogFormat {
+            timestamp: 1_700_000_000,
+            username: "Ann".to_string(),
+            r#type: GourceActionType::M,
+            file: "src/main.rs".to_string(),
+            changeset_size: None,
+            sequence: None,
+            color: None,
+        }
+    }
+
+    #[test]
+    fn archive_spools_are_removed_when_the_run_fails() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let archive = dir.path().join("logs.tar.gz");
+        let config = gource_config(
+            dir.path(),
+            &[
+                "--split-by-author",
+                "authors",
+                "--archive",
+                &archive.to_string_lossy(),
+            ],
+        )?;
+        let mut writer = AuthorArchiveWriter::new(Path::new("authors"), &archive, &config)?;
+        let other = AuthorArchiveWriter::new(Path::new("authors"), &archive, &config)?;
+        assert_ne!(writer.spool.path(), other.spool.path());
+        writer.write(&log())?;
+        let spool = writer.spool.path().to_path_buf();
+        assert!(spool.exists());
+        // Dropped without finishing, the way an error unwinds the write
+        drop(writer);
+        assert!(!spool.exists());
+        assert!(!archive.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn json_records_leave_out_unset_fields() -> anyhow::Result<()> {
+        assert_eq!(
+            serde_json::to_string(&JsonLine::new(&log(), false))?,
+            r#"{"timestamp":1700000000,"username":"Ann","type":"M","file":"src/main.rs"}"#
+        );
+        let log = GourceLogFormat {
+            changeset_size: Some(3),
+            ..log()
+        };
+        assert_eq!(
+            serde_json::to_string(&JsonLine::new(&log, false))?,
+            r#"{"timestamp":1700000000,"username":"Ann","type":"M","file":"src/main.rs","changeset_size":3}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn debug_dates_are_comments_before_each_record() -> anyhow::Result<()> {
+        let fields = [
+            LogField::Timestamp,
+            LogField::Username,
+            LogField::Type,
+            LogField::File,
+        ];
+        let coloured = GourceLogFormat {
+            timestamp: 1_700_003_600,
+            color: Some("F2551B".to_string()),
+            ..log()
+        };
+        let mut writer = gource_writer(Vec::new());
+        write_gource_line(&mut writer, &log(), &fields, true)?;
+        write_gource_line(&mut writer, &coloured, &fields, true)?;
+        write_gource_line(&mut writer, &log(), &fields, false)?;
+        let written = writer
+            .into_inner()
+            .map_err(|e| anyhow::anyhow!("{}", e.error()))?;
+        assert_eq!(
+            String::from_utf8(written)?,
+            "# 2023-11-14T22:13:20Z\n1700000000|Ann|M|src/main.rs\n\
+            # 2023-11-14T23:13:20Z\n1700003600|Ann|M|src/main.rs|F2551B\n\
+            1700000000|Ann|M|src/main.rs\n"
+        );
+
+        let json = serde_json::to_string(&JsonLine::new(&log(), true))?;
+        assert!(json.ends_with(r#""date":"2023-11-14T22:13:20Z"}"#));
+        Ok(())
+    }
+}
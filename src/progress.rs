@@ -0,0 +1,279 @@
+use crate::consts::{
+    DEFAULT_COUNTER_STYLE, DEFAULT_PROGRESS_STYLE, DEFAULT_SPINNER_STYLE,
+    DEFAULT_SPINNER_TICK_STYLE,
+};
+use anyhow::Context;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A `MultiProgress` that also keeps track of the bars added to it,
+/// so their state can be reported somewhere other than the terminal
+#[derive(Clone)]
+pub struct Progress {
+    multi: MultiProgress,
+    bars: Arc<Mutex<Vec<ProgressBar>>>,
+    styles: Arc<ProgressStyles>,
+}
+
+/// How every bar is drawn, the templates are checked up front so a bad one fails before any work is done
+pub struct ProgressStyles {
+    progress: ProgressStyle,
+    spinner: ProgressStyle,
+    counter: ProgressStyle,
+}
+
+impl ProgressStyles {
+    /// Use the given templates in place of the defaults
+    pub fn new(progress: Option<&str>, spinner: Option<&str>) -> anyhow::Result<Self> {
+        let progress = progress.unwrap_or(DEFAULT_PROGRESS_STYLE);
+        let spinner = spinner.unwrap_or(DEFAULT_SPINNER_STYLE);
+        Ok(Self {
+            progress: ProgressStyle::with_template(progress)
+                .context(format!("Invalid progress template {progress:?}"))?,
+            spinner: ProgressStyle::with_template(spinner)
+                .context(format!("Invalid spinner template {spinner:?}"))?
+                .tick_chars(DEFAULT_SPINNER_TICK_STYLE),
+            counter: ProgressStyle::with_template(DEFAULT_COUNTER_STYLE)?
+                .tick_chars(DEFAULT_SPINNER_TICK_STYLE),
+        })
+    }
+}
+
+/// A point-in-time view of a single progress bar
+pub struct ProgressSnapshot {
+    pub phase: String,
+    /// `None` when the bar is a spinner with no known length
+    pub percent: Option<u64>,
+}
+
+impl Progress {
+    pub fn new(styles: ProgressStyles) -> Self {
+        Self {
+            multi: MultiProgress::new(),
+            bars: Arc::new(Mutex::new(Vec::new())),
+            styles: Arc::new(styles),
+        }
+    }
+
+    pub const fn multi(&self) -> &MultiProgress {
+        &self.multi
+    }
+
+    pub fn add(&self, bar: ProgressBar) -> ProgressBar {
+        let bar = self.multi.add(bar);
+        if let Ok(mut bars) = self.bars.lock() {
+            bars.push(bar.clone());
+        }
+        bar
+    }
+
+    /// Add a bar out of `length`
+    pub fn add_bar(&self, length: u64) -> ProgressBar {
+        self.add(ProgressBar::new(length).with_style(self.styles.progress.clone()))
+    }
+
+    /// Add a bar out of `length`, or a spinner that just counts up when the length isn't known
+    pub fn add_counter(&self, length: Option<u64>) -> ProgressBar {
+        if let Some(length) = length {
+            return self.add_bar(length);
+        }
+        let bar = self.add(ProgressBar::new_spinner().with_style(self.styles.counter.clone()));
+        bar.enable_steady_tick(Duration::from_millis(100));
+        bar
+    }
+
+    /// Add a spinner for work with no measurable progress, its message says what's happening
+    pub fn add_spinner(&self) -> ProgressBar {
+        let bar = self.add(ProgressBar::new_spinner().with_style(self.styles.spinner.clone()));
+        bar.enable_steady_tick(Duration::from_millis(100));
+        bar
+    }
+
+    pub fn remove(&self, bar: &ProgressBar) {
+        self.multi.remove(bar);
+        // Bars can't be compared directly, but the prefix is what identifies a phase anyway
+        if let Ok(mut bars) = self.bars.lock() {
+            let prefix = bar.prefix();
+            bars.retain(|b| b.prefix() != prefix);
+        }
+    }
+
+    /// Stop drawing to the terminal, the bars still keep their state
+    pub fn hide(&self) {
+        self.multi.set_draw_target(ProgressDrawTarget::hidden());
+    }
+
+    /// Take a snapshot of every bar that hasn't been removed
+    pub fn snapshot(&self) -> Vec<ProgressSnapshot> {
+        let Ok(bars) = self.bars.lock() else {
+            return Vec::new();
+        };
+        bars.iter()
+            .map(|bar| {
+                let percent = if bar.is_finished() {
+                    Some(100)
+                } else {
+                    bar.length()
+                        .filter(|len| *len > 0)
+                        .map(|len| bar.position().min(len) * 100 / len)
+                };
+                ProgressSnapshot {
+                    phase: bar.prefix(),
+                    percent,
+                }
+            })
+            .collect()
+    }
+
+    /// Periodically call `report` with a snapshot of every bar on a background thread,
+    /// the returned reporter must be stopped to send the final snapshot
+    pub fn spawn_reporter<F>(&self, interval: Duration, mut report: F) -> ProgressReporter
+    where
+        F: FnMut(&[ProgressSnapshot]) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel();
+        let progress = self.clone();
+        let handle = std::thread::spawn(move || {
+            // Wait on the channel rather than sleeping, so stopping doesn't have to wait out the interval
+            loop {
+                report(&progress.snapshot());
+                if stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            report(&progress.snapshot());
+        });
+        ProgressReporter {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// Roughly how far through the run we are, the average of every bar with a known length
+pub fn overall_percent(snapshots: &[ProgressSnapshot]) -> u64 {
+    let known = snapshots
+        .iter()
+        .filter_map(|snapshot| snapshot.percent)
+        .collect::<Vec<_>>();
+    if known.is_empty() {
+        return 0;
+    }
+    known.iter().sum::<u64>() / known.len() as u64
+}
+
+pub struct ProgressReporter {
+    stop: mpsc::Sender<()>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ProgressReporter {
+    pub fn stop(mut self) {
+        // The reporter stops either way, a send only fails if its thread has already gone
+        let _ = self.stop.send(());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Writes progress as `<phase>\t<percent>` lines to a Unix domain socket,
+/// a percent of `-` means the phase has no known length.
+/// Writes are non-blocking, if the reader falls behind the snapshot is dropped.
+#[cfg(unix)]
+pub struct SocketProgressWriter {
+    stream: std::os::unix::net::UnixStream,
+    pending: Vec<u8>,
+}
+
+#[cfg(unix)]
+impl SocketProgressWriter {
+    pub fn connect(path: &std::path::Path) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        let stream = std::os::unix::net::UnixStream::connect(path).context(format!(
+            "Failed to connect to progress socket {}",
+            path.display()
+        ))?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn send(&mut self, snapshots: &[ProgressSnapshot]) {
+        use std::io::Write;
+
+        // Only queue a new snapshot once the last one has been fully written,
+        // this keeps lines intact without ever blocking on a slow reader
+        if self.pending.is_empty() {
+            for snapshot in snapshots {
+                let percent = snapshot
+                    .percent
+                    .map_or_else(|| "-".to_string(), |p| p.to_string());
+                self.pending
+                    .extend_from_slice(format!("{}\t{percent}\n", snapshot.phase).as_bytes());
+            }
+        }
+
+        match self.stream.write(&self.pending) {
+            Ok(written) => {
+                self.pending.drain(..written);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(_) => {
+                // The reader has gone away, stop trying
+                self.pending.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn stopping_the_reporter_doesnt_wait_out_the_interval() -> anyhow::Result<()> {
+        let progress = Progress::new(ProgressStyles::new(None, None)?);
+        progress.hide();
+        let reports = Arc::new(Mutex::new(0));
+        let counted = reports.clone();
+        let reporter = progress.spawn_reporter(Duration::from_mins(1), move |_| {
+            if let Ok(mut reports) = counted.lock() {
+                *reports += 1;
+            }
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        let started = Instant::now();
+        reporter.stop();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // The first snapshot and the final one
+        assert_eq!(*reports.lock().map_err(|e| anyhow::anyhow!("{e}"))?, 2);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_closed_progress_socket_is_ignored() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("progress.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&path)?;
+        let mut writer = SocketProgressWriter::connect(&path)?;
+        drop(listener.accept()?);
+        let snapshots = [ProgressSnapshot {
+            phase: "Reading".to_string(),
+            percent: Some(50),
+        }];
+        // The first write can still succeed before the close is noticed
+        for _ in 0..3 {
+            writer.send(&snapshots);
+        }
+        assert!(writer.pending.is_empty());
+        Ok(())
+    }
+}
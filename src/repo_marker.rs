@@ -0,0 +1,40 @@
+use crate::consts::REPO_MARKER_FILE;
+use crate::structs::{CommitInfo, GourceActionType, GourceLogFormat};
+use std::sync::Mutex;
+
+/// Remembers the earliest record in a repository, so a marker can be added
+/// at the root of the repository when it first appears in the log
+#[derive(Default)]
+pub struct RepoMarker {
+    earliest: Mutex<Option<GourceLogFormat>>,
+}
+
+impl RepoMarker {
+    /// Record the changes from a single commit, commits may arrive in any order
+    pub fn record(&self, info: &CommitInfo, changes: &[GourceLogFormat]) {
+        let Some(first) = changes.iter().min() else {
+            return;
+        };
+        let Ok(mut earliest) = self.earliest.lock() else {
+            return;
+        };
+        let marker = GourceLogFormat {
+            timestamp: first.timestamp,
+            username: first.username.clone(),
+            r#type: GourceActionType::A,
+            file: info.log_path(REPO_MARKER_FILE.to_string()),
+            changeset_size: None,
+            sequence: None,
+            color: None,
+        };
+        // Compared in full so the same author wins a tie however the commits arrive
+        if earliest.as_ref().is_none_or(|e| marker < *e) {
+            *earliest = Some(marker);
+        }
+    }
+
+    /// The marker, unless nothing from the repository made it into the log
+    pub fn finish(self) -> Option<GourceLogFormat> {
+        self.earliest.into_inner().ok().flatten()
+    }
+}
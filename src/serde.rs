@@ -1,25 +1,62 @@
-use crate::consts::DEFAULT_PROGRESS_STYLE;
-use crate::structs::GourceLogFormat;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use crate::consts::{TEMP_FILE_MAGIC, TEMP_FILE_VERSION};
+use crate::progress::Progress;
+use crate::store::LogStore;
+use crate::structs::{escape_line_breaks, GitLogConfig, GourceLogFormat};
+use anyhow::{bail, Context};
+use indicatif::ProgressBar;
 use serde::{Deserialize, Serialize};
-use std::io::{BufWriter, Read, Seek, Write};
-use std::path::PathBuf;
+use std::io::{BufRead, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
 #[derive(Serialize, Deserialize)]
 pub struct DiskGourceLog {
-    pub size: u16,
+    /// Written before the record as a little-endian u32
+    pub size: u32,
     pub data: Vec<u8>,
 }
 
 pub fn log_to_bytes(log: &GourceLogFormat) -> anyhow::Result<DiskGourceLog> {
     let data = serde_cbor::ser::to_vec_packed(&log)?;
     Ok(DiskGourceLog {
-        size: u16::try_from(data.len())?,
+        size: u32::try_from(data.len())?,
         data,
     })
 }
 
+/// How many bytes the length before each record takes up
+const RECORD_SIZE_LEN: usize = size_of::<u32>();
+
+/// How many bytes the temporary file header takes up before the first record
+const TEMP_FILE_HEADER_LEN: u64 = TEMP_FILE_MAGIC.len() as u64 + 2;
+
+/// Start a new temporary file, so it can be recognised when it's appended to or sorted
+pub fn write_temp_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(TEMP_FILE_MAGIC)?;
+    writer.write_all(&TEMP_FILE_VERSION.to_le_bytes())
+}
+
+/// Check the temporary file was written by a compatible version of rource,
+/// leaving the reader at the first record
+pub fn read_temp_header<R: Read>(reader: &mut R, path: &Path) -> anyhow::Result<()> {
+    let mut magic = [0u8; TEMP_FILE_MAGIC.len()];
+    let mut version = [0u8; 2];
+    if reader.read_exact(&mut magic).is_err() || &magic != TEMP_FILE_MAGIC {
+        bail!("{} is not a rource temporary file", path.display());
+    }
+    reader
+        .read_exact(&mut version)
+        .context(format!("{} is not a rource temporary file", path.display()))?;
+    let version = u16::from_le_bytes(version);
+    if version != TEMP_FILE_VERSION {
+        bail!(
+            "{} was written in temporary file format {version}, this version of rource uses format {TEMP_FILE_VERSION}",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
 pub fn batch_log_write<T>(writer: &mut BufWriter<T>, logs: Vec<DiskGourceLog>) -> anyhow::Result<()>
 where
     T: Write,
@@ -39,18 +76,19 @@ where
     Ok(())
 }
 
+/// Reads the records written to the temporary file back in for the merge sort
 pub struct DiskLogReader {
     reader: io::BufReader<fs::File>,
     progress_bar: ProgressBar,
 }
 
 impl DiskLogReader {
-    pub fn new(filename: &PathBuf, multi_progress: &MultiProgress) -> anyhow::Result<Self> {
-        let input_reader = io::BufReader::new(fs::File::open(filename)?);
-        let progress_bar = multi_progress.add(
-            ProgressBar::new(fs::metadata(filename)?.len())
-                .with_style(ProgressStyle::with_template(DEFAULT_PROGRESS_STYLE)?),
+    pub fn new(filename: &PathBuf, multi_progress: &Progress) -> anyhow::Result<Self> {
+        let mut input_reader = io::BufReader::new(
+            fs::File::open(filename).context(format!("Failed to open {}", filename.display()))?,
         );
+        read_temp_header(&mut input_reader, filename)?;
+        let progress_bar = multi_progress.add_bar(fs::metadata(filename)?.len());
         progress_bar.set_prefix("Log Data");
         progress_bar.set_message("Building Chunks");
         Ok(Self {
@@ -62,17 +100,16 @@ impl DiskLogReader {
     pub fn record_count(&mut self) -> anyhow::Result<u64> {
         let mut counter = 0;
         loop {
-            let mut size_bytes = [0u8; 2];
+            let mut size_bytes = [0u8; RECORD_SIZE_LEN];
             if self.reader.read_exact(&mut size_bytes).is_err() {
-                // When we hit EOF, reset the reader and return the counter
-                self.reader.seek(io::SeekFrom::Start(0))?;
+                // When we hit EOF, go back to the first record and return the counter
+                self.reader
+                    .seek(io::SeekFrom::Start(TEMP_FILE_HEADER_LEN))?;
                 return Ok(counter);
-            };
+            }
             // Figure out the size of the object and skip over it
-            let data_size = u16::from_le_bytes(size_bytes) as usize;
-            #[allow(clippy::cast_possible_wrap)]
-            // Reason: Unlikely that we'd ever have an object this big
-            self.reader.seek_relative(data_size as i64)?;
+            let data_size = u32::from_le_bytes(size_bytes);
+            self.reader.seek_relative(i64::from(data_size))?;
             counter += 1;
         }
     }
@@ -88,16 +125,37 @@ impl Iterator for DiskLogReader {
     type Item = Result<GourceLogFormat, io::Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut size_bytes = [0u8; 2];
-        self.reader.read_exact(&mut size_bytes).ok()?;
-        let data_size = u16::from_le_bytes(size_bytes) as usize;
+        // The input only ends cleanly between records, a record cut short is an error
+        match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        let mut size_bytes = [0u8; RECORD_SIZE_LEN];
+        if let Err(e) = self.reader.read_exact(&mut size_bytes) {
+            return Some(Err(truncated(&e)));
+        }
+        let data_size = u32::from_le_bytes(size_bytes) as usize;
         let mut data = vec![0u8; data_size];
-        self.reader.read_exact(&mut data).ok()?;
-        self.progress_bar.inc((data_size + 2) as u64);
-        Some(Ok(serde_cbor::de::from_slice(&data).ok()?))
+        if let Err(e) = self.reader.read_exact(&mut data) {
+            return Some(Err(truncated(&e)));
+        }
+        self.progress_bar.inc((data_size + RECORD_SIZE_LEN) as u64);
+        Some(
+            serde_cbor::de::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        )
     }
 }
 
+/// The error for a record that the end of the temporary file cut short
+fn truncated(e: &io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!("The temporary file ends part way through a record: {e}"),
+    )
+}
+
 pub fn serialize_logs(changes: &[GourceLogFormat]) -> anyhow::Result<Vec<DiskGourceLog>> {
     use rayon::prelude::*;
     changes
@@ -105,3 +163,111 @@ pub fn serialize_logs(changes: &[GourceLogFormat]) -> anyhow::Result<Vec<DiskGou
         .map(log_to_bytes)
         .collect::<anyhow::Result<Vec<_>>>()
 }
+
+/// Read newline delimited JSON records back into the store, applying the same filters as reading from git
+pub fn read_json_logs(
+    filename: &Path,
+    config: &GitLogConfig,
+    store: &LogStore,
+) -> anyhow::Result<()> {
+    let reader = io::BufReader::new(
+        fs::File::open(filename).context(format!("Failed to open {}", filename.display()))?,
+    );
+
+    let mut logs = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut log: GourceLogFormat = serde_json::from_str(&line).context(format!(
+            "Invalid JSON record on line {} of {}",
+            number + 1,
+            filename.display()
+        ))?;
+        log.file = escape_line_breaks(log.file);
+        if let Some(owner) = config.owner_map.as_ref().and_then(|o| o.owner(&log.file)) {
+            log.username = owner.to_string();
+        }
+        if config.allows(log.r#type, &log.file) {
+            logs.push(log);
+        }
+    }
+
+    store.store(logs)?;
+    store.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::ProgressStyles;
+    use crate::structs::GourceActionType;
+
+    #[test]
+    fn long_paths_round_trip_through_the_temporary_file() -> anyhow::Result<()> {
+        // Longer than a u16 length could hold
+        let log = GourceLogFormat {
+            timestamp: 1_700_000_000,
+            username: "Ann".to_string(),
+            r#type: GourceActionType::A,
+            file: format!("{}/file.txt", "d".repeat(70 * 1024)),
+            changeset_size: None,
+            sequence: None,
+            color: None,
+        };
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rource-temp.bin");
+        let mut writer = BufWriter::new(fs::File::create(&path)?);
+        write_temp_header(&mut writer)?;
+        log_write(&mut writer, &log_to_bytes(&log)?)?;
+        writer.flush()?;
+        drop(writer);
+
+        let progress = Progress::new(ProgressStyles::new(None, None)?);
+        progress.hide();
+        let mut reader = DiskLogReader::new(&path, &progress)?;
+        assert_eq!(reader.record_count()?, 1);
+        let logs = reader.collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(logs, [log]);
+        Ok(())
+    }
+
+    #[test]
+    fn a_truncated_temporary_file_is_an_error() -> anyhow::Result<()> {
+        let log = GourceLogFormat {
+            timestamp: 1_700_000_000,
+            username: "Ann".to_string(),
+            r#type: GourceActionType::A,
+            file: "src/main.rs".to_string(),
+            changeset_size: None,
+            sequence: None,
+            color: None,
+        };
+        let bytes = log_to_bytes(&log)?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rource-temp.bin");
+        let progress = Progress::new(ProgressStyles::new(None, None)?);
+        progress.hide();
+        // Cut off in the second record's body, then in its length
+        for cut in [RECORD_SIZE_LEN + 1, 1] {
+            let mut writer = BufWriter::new(fs::File::create(&path)?);
+            write_temp_header(&mut writer)?;
+            log_write(&mut writer, &bytes)?;
+            log_write(&mut writer, &bytes)?;
+            writer.flush()?;
+            drop(writer);
+            let length = fs::metadata(&path)?.len();
+            let whole = (RECORD_SIZE_LEN + bytes.data.len()) as u64;
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(length - whole + cut as u64)?;
+
+            let mut reader = DiskLogReader::new(&path, &progress)?;
+            assert_eq!(reader.next().transpose()?, Some(log.clone()));
+            assert!(reader.next().is_some_and(|record| record.is_err()), "{cut}");
+        }
+        Ok(())
+    }
+}
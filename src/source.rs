@@ -0,0 +1,37 @@
+use crate::git_cli::GitCliSource;
+use crate::git_stuff::LibGit2Source;
+use crate::progress::Progress;
+use crate::store::LogStore;
+use crate::structs::GitLogConfig;
+use clap::ValueEnum;
+use std::path::Path;
+
+/// Somewhere we can read the history of a single repository from
+pub trait RepositorySource: Sync {
+    /// Read the log for the repository at `path` into the store
+    fn read_log(
+        &self,
+        root_path: &Path,
+        path: &Path,
+        store: &LogStore,
+        progress_bar: &Progress,
+        config: &GitLogConfig,
+    ) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Read repositories using libgit2, this is the fastest option
+    Libgit2,
+    /// Parse the output of the system `git log`, slower but doesn't rely on libgit2
+    GitCli,
+}
+
+impl Backend {
+    pub const fn source(self) -> &'static dyn RepositorySource {
+        match self {
+            Self::Libgit2 => &LibGit2Source,
+            Self::GitCli => &GitCliSource,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::structs::GourceLogFormat;
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatsFormat {
+    /// A human readable table
+    Text,
+    /// A single JSON object
+    Json,
+}
+
+pub struct StatsConfig {
+    pub path: PathBuf,
+    pub format: StatsFormat,
+}
+
+/// Counts of the events written to the log
+#[derive(Default)]
+pub struct Stats {
+    total: u64,
+    authors: HashMap<String, u64>,
+    extensions: HashMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct StatsReport {
+    events: u64,
+    authors: Vec<StatsEntry>,
+    extensions: Vec<StatsEntry>,
+}
+
+#[derive(Serialize)]
+struct StatsEntry {
+    name: String,
+    events: u64,
+    percent: f64,
+}
+
+impl Stats {
+    pub fn record(&mut self, log: &GourceLogFormat) {
+        self.total += 1;
+        *self.authors.entry(log.username.clone()).or_default() += 1;
+        *self
+            .extensions
+            .entry(file_extension(&log.file))
+            .or_default() += 1;
+    }
+
+    pub fn write(&self, config: &StatsConfig) -> anyhow::Result<()> {
+        let report = StatsReport {
+            events: self.total,
+            authors: self.entries(&self.authors),
+            extensions: self.entries(&self.extensions),
+        };
+
+        let mut writer = io::BufWriter::new(
+            fs::File::create(&config.path).context("Failed to create stats file")?,
+        );
+        match config.format {
+            StatsFormat::Text => write_text(&mut writer, &report)?,
+            StatsFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, &report)?;
+                writeln!(writer)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Sort by the number of events, busiest first
+    fn entries(&self, counts: &HashMap<String, u64>) -> Vec<StatsEntry> {
+        let mut entries = counts
+            .iter()
+            .map(|(name, events)| StatsEntry {
+                name: name.clone(),
+                events: *events,
+                percent: percent(*events, self.total),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by(|a, b| b.events.cmp(&a.events).then(a.name.cmp(&b.name)));
+        entries
+    }
+}
+
+fn write_text<W: Write>(writer: &mut W, report: &StatsReport) -> anyhow::Result<()> {
+    writeln!(writer, "Events: {}", report.events)?;
+    for (title, entries) in [
+        ("By extension", &report.extensions),
+        ("By author", &report.authors),
+    ] {
+        writeln!(writer)?;
+        writeln!(writer, "{title}:")?;
+        let width = entries
+            .iter()
+            .map(|e| e.name.len())
+            .max()
+            .unwrap_or_default();
+        for entry in entries {
+            writeln!(
+                writer,
+                "  {:<width$}  {:>10}  {:>5.1}%",
+                entry.name, entry.events, entry.percent
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::cast_precision_loss)]
+// Reason: Only used for display, the counts would need to be enormous to lose precision
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    count as f64 * 100.0 / total as f64
+}
+
+/// The lowercase extension of a file, e.g. `rs` for `src/main.rs`
+pub fn file_extension(file: &str) -> String {
+    Path::new(file)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map_or_else(|| "(none)".to_string(), str::to_lowercase)
+}
@@ -0,0 +1,209 @@
+use crate::serde::{
+    batch_log_write, log_to_bytes, log_write, read_temp_header, serialize_logs, write_temp_header,
+};
+use crate::structs::GourceLogFormat;
+use anyhow::{anyhow, Context};
+use deepsize::DeepSizeOf;
+use log::info;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::{fs, io};
+
+/// Where log records go while the repositories are being read,
+/// either kept in memory or written to the temporary file for the merge sort
+pub struct LogStore {
+    memory: Mutex<MemoryLogs>,
+    disk: Mutex<Option<io::BufWriter<fs::File>>>,
+    on_disk: AtomicBool,
+    spill: Option<Spill>,
+}
+
+#[derive(Default)]
+struct MemoryLogs {
+    logs: Vec<GourceLogFormat>,
+    /// Approximate heap size of the logs in bytes
+    size: usize,
+}
+
+/// When to move the in-memory logs to disk
+struct Spill {
+    temp_file: PathBuf,
+    memory_limit: usize,
+}
+
+impl LogStore {
+    /// Keep every record in memory
+    pub fn in_memory() -> Self {
+        Self {
+            memory: Mutex::default(),
+            disk: Mutex::new(None),
+            on_disk: AtomicBool::new(false),
+            spill: None,
+        }
+    }
+
+    /// Write every record to the temporary file, adding to any records already in it
+    pub fn on_disk(temp_file: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            memory: Mutex::default(),
+            disk: Mutex::new(Some(open_temp_file(temp_file)?)),
+            on_disk: AtomicBool::new(true),
+            spill: None,
+        })
+    }
+
+    /// Keep records in memory until they use more than `memory_limit` bytes,
+    /// then move them all to the temporary file and carry on from there
+    pub fn spill_to_disk(temp_file: PathBuf, memory_limit: usize) -> Self {
+        Self {
+            spill: Some(Spill {
+                temp_file,
+                memory_limit,
+            }),
+            ..Self::in_memory()
+        }
+    }
+
+    /// Have the records ended up in the temporary file?
+    pub fn is_on_disk(&self) -> bool {
+        self.on_disk.load(Ordering::Acquire)
+    }
+
+    /// Store the changes from a single commit
+    pub fn store(&self, mut changes: Vec<GourceLogFormat>) -> anyhow::Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        if self.is_on_disk() {
+            let changes = serialize_logs(&changes[..])?;
+            let mut disk = self
+                .disk
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock writer - {:?}", e))?;
+            let writer = disk
+                .as_mut()
+                .ok_or_else(|| anyhow!("Temporary file has not been opened"))?;
+            let result = batch_log_write(writer, changes);
+            drop(disk);
+            return result;
+        }
+
+        let mut memory = self
+            .memory
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock logs - {:?}", e))?;
+        memory.size += changes.iter().map(DeepSizeOf::deep_size_of).sum::<usize>();
+        memory.logs.append(&mut changes);
+
+        if let Some(spill) = &self.spill {
+            if memory.size > spill.memory_limit {
+                info!(
+                    "Logs are using more than {} MB of memory, switching to the merge sort",
+                    spill.memory_limit / 1024 / 1024
+                );
+                self.move_to_disk(&mut memory, &spill.temp_file)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the in-memory logs to the temporary file, opening it if needed
+    fn move_to_disk(&self, memory: &mut MemoryLogs, temp_file: &Path) -> anyhow::Result<()> {
+        let mut disk = self
+            .disk
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock writer - {:?}", e))?;
+        if disk.is_none() {
+            *disk = Some(open_temp_file(temp_file)?);
+        }
+        let writer = disk
+            .as_mut()
+            .ok_or_else(|| anyhow!("Temporary file has not been opened"))?;
+
+        // Serialise on this thread, we're holding the locks
+        for log in memory.logs.drain(..) {
+            log_write(writer, &log_to_bytes(&log)?)?;
+        }
+        drop(disk);
+        memory.logs.shrink_to_fit();
+        memory.size = 0;
+        self.on_disk.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Roughly how big the records are so far, the size of the temporary file once they're on disk,
+    /// otherwise the estimated memory they're using
+    pub fn size(&self) -> anyhow::Result<u64> {
+        if self.is_on_disk() {
+            self.flush()?;
+            let disk = self
+                .disk
+                .lock()
+                .map_err(|e| anyhow!("Failed to lock writer - {:?}", e))?;
+            let size = disk
+                .as_ref()
+                .map_or(Ok(0), |writer| writer.get_ref().metadata().map(|m| m.len()));
+            drop(disk);
+            return Ok(size?);
+        }
+        let memory = self
+            .memory
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock logs - {:?}", e))?;
+        Ok(memory.size as u64)
+    }
+
+    /// Flush any buffered writes to the temporary file
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let mut disk = self
+            .disk
+            .lock()
+            .map_err(|e| anyhow!("Failed to lock writer for buffer flush - {:?}", e))?;
+        let result = disk.as_mut().map_or(Ok(()), Write::flush);
+        drop(disk);
+        Ok(result?)
+    }
+
+    /// Finish storing records and return the ones kept in memory,
+    /// if the records went to disk this is empty and everything is in the temporary file
+    pub fn finish(self) -> anyhow::Result<Vec<GourceLogFormat>> {
+        let mut memory = self
+            .memory
+            .into_inner()
+            .map_err(|e| anyhow!("Failed to collect logs - {:?}", e))?;
+        if !self.on_disk.load(Ordering::Acquire) {
+            return Ok(memory.logs);
+        }
+
+        // A commit may have been stored in memory while another was moving everything to disk
+        let mut disk = self
+            .disk
+            .into_inner()
+            .map_err(|e| anyhow!("Failed to lock writer for buffer flush - {:?}", e))?;
+        if let Some(writer) = disk.as_mut() {
+            batch_log_write(writer, serialize_logs(&memory.logs[..])?)?;
+            memory.logs.clear();
+            writer.flush()?;
+        }
+        Ok(memory.logs)
+    }
+}
+
+/// Open the temporary file for appending, a new file gets a header and an existing one must have a valid header
+fn open_temp_file(temp_file: &Path) -> anyhow::Result<io::BufWriter<fs::File>> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(temp_file)
+        .context(format!("Failed to open {}", temp_file.display()))?;
+    if file.metadata()?.len() == 0 {
+        write_temp_header(&mut file)?;
+    } else {
+        read_temp_header(&mut file, temp_file)?;
+    }
+    Ok(io::BufWriter::new(file))
+}
@@ -1,25 +1,65 @@
+use crate::activity::ActivityConfig;
+use crate::consts::{CO_AUTHOR_TRAILER, EMPTY_COMMIT_MARKER, TEMPORARY_LOG_FILENAME};
+use crate::git_stuff::repository_root;
+use crate::identities;
+use crate::output::{ColorBy, LogField, OutputCompression, OutputFormat};
+use crate::source::Backend;
+use crate::stats::StatsConfig;
 use anyhow::{anyhow, bail};
+use clap::ValueEnum;
 use deepsize::DeepSizeOf;
-use git2::{Commit, Delta, DiffDelta, Repository};
+use git2::{Commit, Delta, DiffDelta, DiffFile, Mailmap, Repository, Signature};
+use globset::GlobSet;
+use log::info;
 use rand::Rng;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, DeepSizeOf)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    DeepSizeOf,
+    ValueEnum,
+)]
 pub enum GourceActionType {
+    #[value(name = "A")]
     A,
+    #[value(name = "M")]
     M,
+    #[value(name = "D")]
     D,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, DeepSizeOf)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, DeepSizeOf)]
 pub struct GourceLogFormat {
     pub timestamp: i64,
     pub username: String,
     pub r#type: GourceActionType,
     pub file: String,
+    /// How many files the commit changed in total, only set with `--emit-changeset-size`.
+    /// Not skipped when it's `None`, the merge sort's formats are positional
+    #[serde(default)]
+    pub changeset_size: Option<u64>,
+    /// Where the commit came in its repository's history, only set with `--preserve-commit-order`
+    #[serde(default)]
+    pub sequence: Option<i64>,
+    /// Gource's optional colour column, a hex colour like `FF0000`. Only set with `--color-by`,
+    /// when the log is written
+    #[serde(default)]
+    pub color: Option<String>,
 }
 
 impl PartialOrd for GourceLogFormat {
@@ -32,6 +72,7 @@ impl Ord for GourceLogFormat {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.timestamp
             .cmp(&other.timestamp)
+            .then(self.sequence.cmp(&other.sequence))
             .then(self.file.cmp(&other.file))
             .then(self.r#type.cmp(&other.r#type))
             .then(self.username.cmp(&other.username))
@@ -40,84 +81,1058 @@ impl Ord for GourceLogFormat {
 
 impl GourceLogFormat {
     pub fn try_from_delta(
-        root_path: &PathBuf,
-        repo: &Repository,
-        commit: &Commit<'_>,
+        info: &CommitInfo,
         delta: &'_ DiffDelta<'_>,
-    ) -> anyhow::Result<Option<Self>> {
-        // Using the root path, determine the relative path to the repository
-        let relative = repo
-            .path()
-            .strip_prefix(root_path)
-            .map_err(|e| {
-                anyhow!(
-                    "Unable to determine relative path for {:?}: {e}",
-                    repo.path()
-                )
-            })?
-            .parent()
-            .ok_or_else(|| anyhow!("Git repo has no parent path? {:?}", repo.path()))?;
-
-        let username = commit
-            .author()
-            .name()
-            .ok_or_else(|| anyhow!("Unable to parse git log for {:?}", commit))?
-            .replace('|', "#");
-
+        config: &GitLogConfig,
+    ) -> anyhow::Result<Vec<Self>> {
         let r#type = match delta.status() {
             Delta::Added => GourceActionType::A,
-            Delta::Deleted => GourceActionType::D,
-            Delta::Modified | Delta::Renamed | Delta::Copied | Delta::Typechange => {
-                GourceActionType::M
+            // The new path is left to appear when it's next changed
+            Delta::Renamed if config.rename_emit_delete_only => GourceActionType::D,
+            // A move is shown as the old path being deleted and the new one added
+            Delta::Renamed => {
+                let old = Self::delta_path(info, &delta.old_file())?;
+                let new = Self::delta_path(info, &delta.new_file())?;
+                return Ok([(GourceActionType::D, old), (GourceActionType::A, new)]
+                    .into_iter()
+                    .filter_map(|(r#type, path)| info.to_log(r#type, path, config))
+                    .collect());
             }
+            Delta::Deleted => GourceActionType::D,
+            // A copy creates the new path, so it can be shown as one
+            Delta::Copied if config.copy_as_added => GourceActionType::A,
+            Delta::Modified | Delta::Copied | Delta::Typechange => GourceActionType::M,
             // These don't change the tree so they're NOPs
             Delta::Untracked
             | Delta::Unmodified
             | Delta::Unreadable
             | Delta::Conflicted
             | Delta::Ignored => {
-                return Ok(None);
+                return Ok(vec![]);
             }
         };
 
-        let path = delta
-            .new_file()
-            .path()
-            .ok_or_else(|| anyhow!("Unable to parse git log for {:?}", commit))?
+        let file = if r#type == GourceActionType::D {
+            delta.old_file()
+        } else {
+            delta.new_file()
+        };
+        let path = Self::delta_path(info, &file)?;
+
+        // A chmod shows up as a modification, but the content hasn't changed
+        if config.skip_mode_only_changes
+            && delta.status() == Delta::Modified
+            && delta.old_file().id() == delta.new_file().id()
+            && delta.old_file().mode() != delta.new_file().mode()
+        {
+            info.explain(path, FilterReason::ModeOnlyChange, config);
+            return Ok(vec![]);
+        }
+
+        Ok(info.to_log(r#type, path, config).into_iter().collect())
+    }
+
+    fn delta_path<'a>(info: &CommitInfo, file: &DiffFile<'a>) -> anyhow::Result<&'a str> {
+        file.path()
+            .ok_or_else(|| anyhow!("Unable to parse git log for {}", info.id))?
             .to_str()
-            .ok_or_else(|| anyhow!("Unable to parse git log for {:?}", commit))?
-            .to_string();
+            .ok_or_else(|| anyhow!("Unable to parse git log for {}", info.id))
+    }
+}
 
-        let file = if relative.as_os_str() == "" {
-            path
+/// The name and email the mailmap gives a signature, or the signature as it is without one
+pub fn mailmapped<'a>(signature: Signature<'a>, mailmap: Option<&Mailmap>) -> Signature<'a> {
+    mailmap
+        .and_then(|mailmap| mailmap.resolve_signature(&signature).ok())
+        .unwrap_or(signature)
+}
+
+/// The parts of a log record that are shared by every file changed in a commit
+pub struct CommitInfo {
+    pub id: String,
+    /// Path of the repository relative to the root, prepended to every file
+    pub prefix: String,
+    pub timestamp: i64,
+    pub username: String,
+    /// The committer when they aren't the author, only set with `--identity both`
+    pub committer: Option<String>,
+    /// Everyone named in a `Co-authored-by:` trailer, apart from the author and committer
+    pub co_authors: Vec<String>,
+    pub is_root: bool,
+    /// Files tracked by Git LFS in this repository, only set with `--skip-lfs`
+    pub lfs_filter: Option<Arc<GlobSet>>,
+    /// Files excluded by the repository's own options
+    pub excludes: Option<Arc<GlobSet>>,
+}
+
+impl CommitInfo {
+    pub fn from_commit(
+        root_path: &Path,
+        repo: &Repository,
+        commit: &Commit<'_>,
+        mailmap: Option<&Mailmap>,
+        config: &GitLogConfig,
+    ) -> anyhow::Result<Self> {
+        let workdir = repository_root(repo);
+
+        let author = match commit.parent(1) {
+            Ok(merged) if config.merge_attribution == MergeAttribution::FirstParentAuthor => {
+                merged.author().to_owned()
+            }
+            _ => commit.author(),
+        };
+        let (author, committer) = config.identity.pick(
+            mailmapped(author, mailmap),
+            mailmapped(commit.committer(), mailmap),
+        );
+        let signature_username = |signature: &git2::Signature<'_>| {
+            signature
+                .name()
+                .map(|name| Self::username(name, signature.email(), config))
+                .ok_or_else(|| anyhow!("Unable to parse git log for {:?}", commit))
+        };
+
+        let info = Self::new(
+            root_path,
+            workdir,
+            commit.id().to_string(),
+            config.date_source.time(commit),
+            &signature_username(&author)?,
+            commit.parent_count() == 0,
+            config,
+        )?;
+        let info = match committer {
+            Some(committer) => {
+                info.with_committer(workdir, &signature_username(&committer)?, config)
+            }
+            None => info,
+        };
+        if !config.shows_co_authors() {
+            return Ok(info);
+        }
+        let message = String::from_utf8_lossy(commit.message_bytes());
+        let trailers = git2::message_trailers_strs(&message)?;
+        Ok(info.with_co_authors(
+            workdir,
+            trailers
+                .iter()
+                .filter(|(key, _)| key.eq_ignore_ascii_case(CO_AUTHOR_TRAILER))
+                .map(|(_, value)| value),
+            config,
+        ))
+    }
+
+    /// Pick the name to show for an author, their name, their email or their email domain
+    pub fn username(name: &str, email: Option<&str>, config: &GitLogConfig) -> String {
+        identities::record(name, email);
+        if config.author_email {
+            // Fall back to the name if there's no email
+            if let Some(email) = email.map(str::trim).filter(|email| !email.is_empty()) {
+                return email.to_string();
+            }
+        }
+        if config.group_by_email_domain {
+            // Fall back to the name if there's no usable domain
+            if let Some(domain) = email
+                .and_then(|email| email.rsplit_once('@'))
+                .map(|(_, domain)| domain.trim().to_lowercase())
+                .filter(|domain| !domain.is_empty())
+            {
+                return domain;
+            }
+        }
+        name.to_string()
+    }
+
+    pub fn new(
+        root_path: &Path,
+        workdir: &Path,
+        id: String,
+        timestamp: i64,
+        username: &str,
+        is_root: bool,
+        config: &GitLogConfig,
+    ) -> anyhow::Result<Self> {
+        let options = config.repository_options.get(workdir);
+        let prefix = if config.absolute_paths {
+            workdir
+                .to_str()
+                .ok_or_else(|| anyhow!("Unable to parse git path for {:?}", workdir))?
+                .trim_end_matches('/')
+                .to_string()
+        } else if let Some(prefix) = options.and_then(|o| o.prefix.clone()) {
+            prefix
         } else {
-            format!(
-                "{}/{}",
+            // Using the root path, determine the relative path to the repository
+            let relative = workdir.strip_prefix(root_path).map_err(|e| {
+                anyhow!(
+                    "Unable to determine relative path for {}: {e}",
+                    workdir.display()
+                )
+            })?;
+            // A repository at the root has no path of its own
+            let relative = if relative.as_os_str().is_empty() && config.prefix_repo_name {
+                workdir.file_name().map_or(relative, Path::new)
+            } else {
                 relative
-                    .to_str()
-                    .ok_or_else(|| anyhow!("Unable to parse git path for {:?}", relative))?,
-                path
-            )
+            };
+
+            relative
+                .to_str()
+                .ok_or_else(|| anyhow!("Unable to parse git path for {:?}", relative))?
+                .to_string()
+        };
+
+        Ok(Self {
+            id,
+            prefix,
+            timestamp: timestamp + options.map_or(0, |o| o.time_offset),
+            username: Self::display_name(options, username, config),
+            committer: None,
+            co_authors: Vec::new(),
+            is_root,
+            lfs_filter: None,
+            excludes: options.and_then(|o| o.excludes.clone()),
+        })
+    }
+
+    /// The name written to the log, after the repository's own aliases
+    fn display_name(
+        options: Option<&RepositoryOptions>,
+        username: &str,
+        config: &GitLogConfig,
+    ) -> String {
+        options
+            .and_then(|o| {
+                if config.case_insensitive_authors {
+                    let username = username.to_lowercase();
+                    o.aliases
+                        .iter()
+                        .find(|(from, _)| from.to_lowercase() == username)
+                        .map(|(_, to)| to)
+                } else {
+                    o.aliases.get(username)
+                }
+            })
+            .map_or(username, String::as_str)
+            .replace('|', "#")
+    }
+
+    /// Also attribute the commit to its committer, unless they're the author
+    pub fn with_committer(
+        mut self,
+        workdir: &Path,
+        committer: &str,
+        config: &GitLogConfig,
+    ) -> Self {
+        let committer =
+            Self::display_name(config.repository_options.get(workdir), committer, config);
+        self.committer = Some(committer).filter(|committer| *committer != self.username);
+        self
+    }
+
+    /// Also attribute the commit to the people named in its `Co-authored-by:` trailers,
+    /// each trailer is either `Name <email>` or just a name
+    pub fn with_co_authors<'a>(
+        mut self,
+        workdir: &Path,
+        trailers: impl IntoIterator<Item = &'a str>,
+        config: &GitLogConfig,
+    ) -> Self {
+        let options = config.repository_options.get(workdir);
+        for trailer in trailers {
+            let trailer = trailer.trim();
+            let (name, email) = match trailer.split_once('<') {
+                Some((name, email)) => (name.trim(), email.trim_end().strip_suffix('>')),
+                None => (trailer, None),
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let co_author =
+                Self::display_name(options, &Self::username(name, email, config), config);
+            if co_author != self.username
+                && self.committer.as_ref() != Some(&co_author)
+                && !self.co_authors.contains(&co_author)
+            {
+                self.co_authors.push(co_author);
+            }
+        }
+        self
+    }
+
+    /// Repeat the author's changes for the committer with `--identity both`, then for each co-author.
+    /// Changes given to an owner by --owner-map aren't repeated
+    pub fn add_shared_changes(&self, mut changes: Vec<GourceLogFormat>) -> Vec<GourceLogFormat> {
+        let copies = self
+            .committer
+            .iter()
+            .chain(&self.co_authors)
+            .flat_map(|username| {
+                changes
+                    .iter()
+                    .filter(|change| change.username == self.username)
+                    .map(|change| GourceLogFormat {
+                        username: username.clone(),
+                        ..change.clone()
+                    })
+            })
+            .collect::<Vec<_>>();
+        changes.extend(copies);
+        changes
+    }
+
+    pub fn with_lfs_filter(mut self, lfs_filter: Option<Arc<GlobSet>>) -> Self {
+        self.lfs_filter = lfs_filter;
+        self
+    }
+
+    /// Turn a change to a file in this commit into a log record, unless it's filtered out
+    pub fn to_log(
+        &self,
+        r#type: GourceActionType,
+        path: &str,
+        config: &GitLogConfig,
+    ) -> Option<GourceLogFormat> {
+        let file = self.log_path(path.to_string());
+        if !self.should_include(r#type, path, &file, config) {
+            return None;
+        }
+        // When summarising the initial import, only keep the top-level entry of each path
+        // that passed the filters, they need the real path to match against
+        if config.root_commit_mode == RootCommitMode::Summarize && self.is_root {
+            let top_level = path.split('/').next().unwrap_or_default();
+            return Some(self.new_log(r#type, self.log_path(top_level.to_string()), config));
+        }
+        Some(self.new_log(r#type, file, config))
+    }
+
+    /// The one place file events are filtered, the filters are checked in this order and the first one
+    /// that matches leaves the event out: --only-types, --skip-lfs, the repository's own excludes,
+    /// --path-exclude, --path-include, then --sensible-defaults. `path` is the file's path in the repository and `file` is its path in the log.
+    /// Mode-only changes are left out before this, they need the diff to tell
+    pub fn should_include(
+        &self,
+        r#type: GourceActionType,
+        path: &str,
+        file: &str,
+        config: &GitLogConfig,
+    ) -> bool {
+        let reason = if !config.allows_type(r#type) {
+            Some(FilterReason::ActionType)
+        } else if self.lfs_filter.as_ref().is_some_and(|f| f.is_match(path)) {
+            Some(FilterReason::LfsFile)
+        } else if self.excludes.as_ref().is_some_and(|f| f.is_match(path)) {
+            Some(FilterReason::RepositoryExclude)
+        } else {
+            config.path_filter.reason(file)
         };
+        let Some(reason) = reason else {
+            return true;
+        };
+        self.explain(path, reason, config);
+        false
+    }
+
+    /// Say why a file was left out, for --explain-filters. `path` is the file's path in the repository
+    pub fn explain(&self, path: &str, reason: FilterReason, config: &GitLogConfig) {
+        if config.explain_filters {
+            info!(
+                "Left out {} in {}: {}",
+                self.log_path(path.to_string()),
+                self.id,
+                reason.description()
+            );
+        }
+    }
+
+    /// Where a file in the repository appears in the log
+    pub fn log_path(&self, path: String) -> String {
+        let file = if self.prefix.is_empty() {
+            path
+        } else {
+            format!("{}/{path}", self.prefix)
+        };
+        escape_line_breaks(file)
+    }
+
+    /// A record standing in for a commit that didn't change any files, so it still shows on the timeline
+    pub fn empty_commit_marker(&self, config: &GitLogConfig) -> Option<GourceLogFormat> {
+        self.log_for_file(
+            GourceActionType::M,
+            self.log_path(EMPTY_COMMIT_MARKER.to_string()),
+            config,
+        )
+    }
 
-        Ok(Some(Self {
-            timestamp: commit.time().seconds(),
-            username,
+    /// Make a log record for a file already in log form, unless it's filtered out
+    pub fn log_for_file(
+        &self,
+        r#type: GourceActionType,
+        file: String,
+        config: &GitLogConfig,
+    ) -> Option<GourceLogFormat> {
+        if !config.allows(r#type, &file) {
+            return None;
+        }
+        Some(self.new_log(r#type, file, config))
+    }
+
+    /// A record for a file in this commit, given to its owner if --owner-map has one
+    fn new_log(
+        &self,
+        r#type: GourceActionType,
+        file: String,
+        config: &GitLogConfig,
+    ) -> GourceLogFormat {
+        let username = config
+            .owner_map
+            .as_ref()
+            .and_then(|owners| owners.owner(&file))
+            .unwrap_or(&self.username);
+
+        GourceLogFormat {
+            timestamp: self.timestamp,
+            username: username.to_string(),
             r#type,
             file,
-        }))
+            changeset_size: None,
+            sequence: None,
+            color: None,
+        }
+    }
+}
+
+/// Filenames can contain line breaks, which would split a record across lines in the log,
+/// so replace them with a visible escape sequence instead
+pub fn escape_line_breaks(file: String) -> String {
+    if file.contains(['\n', '\r']) {
+        file.replace('\n', "\\n").replace('\r', "\\r")
+    } else {
+        file
+    }
+}
+
+/// Options that control how commits are read from each repository
+#[allow(clippy::struct_excessive_bools)]
+// Reason: These are independent options, not a state machine
+pub struct GitLogConfig {
+    pub max_changeset_size: Option<usize>,
+    pub path_filter: PathFilter,
+    /// Only emit these action types, empty means all of them
+    pub only_types: Vec<GourceActionType>,
+    /// How many repositories to process at once, `None` leaves it up to rayon
+    pub repo_concurrency: Option<NonZeroUsize>,
+    pub skip_mode_only_changes: bool,
+    pub root_commit_mode: RootCommitMode,
+    pub backend: Backend,
+    /// Use the domain of the author's email as their username
+    pub group_by_email_domain: bool,
+    /// Use the author's email as their username
+    pub author_email: bool,
+    /// Drop files tracked by Git LFS
+    pub skip_lfs: bool,
+    /// Record the total number of files changed by each commit on its records
+    pub emit_changeset_size: bool,
+    /// How many of the newest commits in each repository to leave out
+    pub skip_recent: usize,
+    /// Read the commits on every local branch, not just the ones reachable from the start point
+    pub all_refs: bool,
+    /// Only follow the first parent of each merge, like `git log --first-parent`
+    pub first_parent: bool,
+    /// Only read commits made at or after this unix time
+    pub since: Option<i64>,
+    /// Only read commits made at or before this unix time
+    pub until: Option<i64>,
+    /// Read one repository at a time, and each repository's commits in order on a single thread
+    pub sequential: bool,
+    /// Count the commits up front so the progress bars know how long they are
+    pub progress_count: bool,
+    /// Delete files that were never shown as deleted but aren't in HEAD
+    pub emit_deletes_for_final_state: bool,
+    /// Add a file at the root of each repository at the time of its first commit
+    pub emit_repo_markers: bool,
+    /// Map names and emails through each repository's .mailmap
+    pub use_mailmap: bool,
+    /// Also attribute commits to the people in their `Co-authored-by:` trailers
+    pub co_authors: bool,
+    /// Show detected copies as the new file being added
+    pub copy_as_added: bool,
+    /// Only read every Nth commit, for quick previews
+    pub sample_commits: NonZeroUsize,
+    /// Fail instead of warning when a repository looks corrupt
+    pub strict: bool,
+    /// Keep commits from the same second in history order rather than sorting them by file
+    pub preserve_commit_order: bool,
+    /// Start every file with its repository's absolute path instead of a relative one
+    pub absolute_paths: bool,
+    /// Start the files of a repository at the root with its directory name, rather than nothing
+    pub prefix_repo_name: bool,
+    /// Show commits that didn't change any files as a change to a marker file, rather than leaving them out
+    pub keep_empty_commits: bool,
+    /// Show a detected rename as just the old path being deleted
+    pub rename_emit_delete_only: bool,
+    /// Detect renames, which are still shown as a delete and an add
+    pub find_renames: bool,
+    /// Who commits are attributed to
+    pub identity: Identity,
+    /// Show each commit at its author or committer date
+    pub date_source: DateSource,
+    /// Who merge commits are attributed to
+    pub merge_attribution: MergeAttribution,
+    /// Log why each file event is left out
+    pub explain_filters: bool,
+    /// Commits to leave out, full or abbreviated ids
+    pub excluded_commits: Vec<String>,
+    /// Only read the commits of these authors, matched after the mailmap but before any aliases
+    pub author_filter: AuthorFilter,
+    /// Leave out commits with more than one parent
+    pub no_merges: bool,
+    /// Only read commits with more than one parent
+    pub merges_only: bool,
+    /// Leave out repositories that fail to read instead of stopping
+    pub keep_going: bool,
+    /// Add every file in this ref's tree instead of reading the history
+    pub snapshot_ref: Option<String>,
+    /// Which commit the history is read back from
+    pub start_point: StartPoint,
+    /// Leave out this commit and everything reachable from it
+    pub since_commit: Option<String>,
+    /// Drop repeated events for the same user, file and action within a commit
+    pub dedupe_within_commit: bool,
+    /// Match names and aliases ignoring case, the global alias keys are lowercase
+    pub case_insensitive_authors: bool,
+    /// The global aliases, these are applied when the log is written but
+    /// --dedupe-within-commit needs to know which usernames end up the same
+    pub aliases: HashMap<String, String>,
+    /// The --alias-regex patterns, tried when a username has no alias
+    pub alias_patterns: Vec<AliasPattern>,
+    pub owner_map: Option<OwnerMap>,
+    /// Overrides for individual repositories from a `--job`, keyed by their canonical path
+    pub repository_options: HashMap<PathBuf, RepositoryOptions>,
+}
+
+/// Settings for a single repository that the command line can't express
+#[derive(Clone, Default)]
+pub struct RepositoryOptions {
+    /// Replaces the repository's relative path in the log
+    pub prefix: Option<String>,
+    /// Seconds added to the time of every commit
+    pub time_offset: i64,
+    pub aliases: HashMap<String, String>,
+    /// Files to leave out, matched against their path in the repository
+    pub excludes: Option<Arc<GlobSet>>,
+}
+
+/// How to handle the commit(s) with no parents, which usually add the entire initial import
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RootCommitMode {
+    /// Emit every file in the root commit
+    Full,
+    /// Emit a single Added event for each top-level file or directory in the root commit
+    Summarize,
+    /// Don't emit anything for the root commit
+    Skip,
+}
+
+/// Why a file event was left out of the log, for --explain-filters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterReason {
+    ModeOnlyChange,
+    ActionType,
+    LfsFile,
+    RepositoryExclude,
+    PathExclude,
+    PathInclude,
+    SensibleDefaults,
+}
+
+impl FilterReason {
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::ModeOnlyChange => "only its mode changed (--skip-mode-only-changes)",
+            Self::ActionType => "its action isn't one of --only-types",
+            Self::LfsFile => "it's tracked by Git LFS (--skip-lfs)",
+            Self::RepositoryExclude => "it's excluded by the repository's own options",
+            Self::PathExclude => "it matches one of the --path-exclude patterns",
+            Self::PathInclude => "it doesn't match any of the --path-include patterns",
+            Self::SensibleDefaults => "it matches one of the --sensible-defaults patterns",
+        }
+    }
+}
+
+/// Where each repository's history is read from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StartPoint {
+    /// Whatever is checked out
+    #[default]
+    Head,
+    /// The branch the repository's remote calls its default, otherwise `init.defaultBranch`
+    DefaultBranch,
+    /// A local branch, every repository must have it
+    Branch(String),
+    /// A commit given by its SHA, every repository must have it
+    Commit(String),
+}
+
+/// Who a commit's changes are attributed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Identity {
+    /// The person who wrote the change
+    Author,
+    /// The person who committed it, e.g. whoever merged or rebased it
+    Committer,
+    /// Both of them, commits where they differ are shown twice
+    Both,
+}
+
+impl Identity {
+    /// Choose between a commit's author and committer, the second is only set for `Both`
+    pub fn pick<T>(self, author: T, committer: T) -> (T, Option<T>) {
+        match self {
+            Self::Author => (author, None),
+            Self::Committer => (committer, None),
+            Self::Both => (author, Some(committer)),
+        }
+    }
+}
+
+/// Which of a commit's dates it's shown at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DateSource {
+    /// When the change was written, kept when it's rebased or cherry-picked
+    Author,
+    /// When it was committed, i.e. when it landed on the branch
+    Committer,
+}
+
+impl DateSource {
+    /// The commit's date in unix time
+    pub fn time(self, commit: &Commit<'_>) -> i64 {
+        match self {
+            Self::Author => commit.author().when().seconds(),
+            Self::Committer => commit.time().seconds(),
+        }
+    }
+}
+
+/// Who the changes in a merge commit are attributed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MergeAttribution {
+    /// The author of the merge commit
+    Merger,
+    /// The author of the work that was merged in, the tip of the merge's second parent
+    FirstParentAuthor,
+}
+
+/// The order repositories are read in, so runs are the same on every filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RepositoryOrder {
+    /// By the name of the repository's directory
+    Name,
+    /// By the repository's full path
+    Path,
+    /// By when the repository's directory was last modified, oldest first
+    Mtime,
+}
+
+impl GitLogConfig {
+    /// Should a file event with this action type and path be kept in the log?
+    /// These are the filters that don't depend on the repository, for records that aren't read from git
+    pub fn allows(&self, r#type: GourceActionType, file: &str) -> bool {
+        let reason = if !self.allows_type(r#type) {
+            FilterReason::ActionType
+        } else if let Some(reason) = self.path_filter.reason(file) {
+            reason
+        } else {
+            return true;
+        };
+        if self.explain_filters {
+            info!("Left out {file}: {}", reason.description());
+        }
+        false
+    }
+
+    /// The name a username ends up as in the log for comparing, after the global aliases.
+    /// With --case-insensitive-authors it's lowercase, the casing that's written isn't known yet
+    pub fn comparable_username(&self, username: &str) -> String {
+        if !self.case_insensitive_authors {
+            return self
+                .aliases
+                .get(username)
+                .cloned()
+                .or_else(|| AliasPattern::resolve(&self.alias_patterns, username))
+                .unwrap_or_else(|| username.to_string());
+        }
+        let lowercase = username.to_lowercase();
+        self.aliases
+            .get(&lowercase)
+            .cloned()
+            .or_else(|| AliasPattern::resolve(&self.alias_patterns, username))
+            .map_or(lowercase, |alias| alias.to_lowercase())
+    }
+
+    /// Is this action type one of the --only-types?
+    pub fn allows_type(&self, r#type: GourceActionType) -> bool {
+        self.only_types.is_empty() || self.only_types.contains(&r#type)
+    }
+
+    /// Is a commit read, given whether it's a merge? For --no-merges and --merges-only
+    pub const fn reads_commit(&self, is_merge: bool) -> bool {
+        if is_merge {
+            !self.no_merges
+        } else {
+            !self.merges_only
+        }
+    }
+
+    /// Was a commit made at this unix time within --since and --until?
+    pub fn in_date_range(&self, time: i64) -> bool {
+        self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time <= until)
     }
+
+    /// Are co-authors shown? They're authors, so not when commits are attributed to their committer
+    pub fn shows_co_authors(&self) -> bool {
+        self.co_authors && self.identity != Identity::Committer
+    }
+
+    /// Should renames be detected rather than left as an unrelated delete and add?
+    pub const fn detect_renames(&self) -> bool {
+        self.find_renames || self.rename_emit_delete_only
+    }
+
+    /// Should copies be detected? Only --copy-as-added shows them differently from an added file
+    pub const fn detect_copies(&self) -> bool {
+        self.copy_as_added
+    }
+}
+
+/// Maps directories to the team that owns them, for `--owner-map`
+pub struct OwnerMap {
+    /// Longest prefix first, so the first match is the most specific
+    owners: Vec<(String, String)>,
 }
 
+impl OwnerMap {
+    pub fn new(mut owners: Vec<(String, String)>) -> Self {
+        owners.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Self { owners }
+    }
+
+    /// The owner of the longest directory prefix containing the file
+    pub fn owner(&self, file: &str) -> Option<&str> {
+        self.owners
+            .iter()
+            .find(|(prefix, _)| {
+                file.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| prefix.is_empty() || rest.starts_with('/'))
+            })
+            .map(|(_, owner)| owner.as_str())
+    }
+}
+
+/// Decides which commits are read by their author, for --author and --exclude-author
+pub struct AuthorFilter {
+    include: HashSet<String>,
+    exclude: HashSet<String>,
+    /// The names are lowercase when this is set
+    ignore_case: bool,
+}
+
+impl AuthorFilter {
+    pub fn new(include: &[String], exclude: &[String], ignore_case: bool) -> Self {
+        let names = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| {
+                    if ignore_case {
+                        name.to_lowercase()
+                    } else {
+                        name.clone()
+                    }
+                })
+                .collect()
+        };
+        Self {
+            include: names(include),
+            exclude: names(exclude),
+            ignore_case,
+        }
+    }
+
+    /// Are this author's commits read? An author in both lists is left out
+    pub fn allows(&self, name: &str) -> bool {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return true;
+        }
+        let name = if self.ignore_case {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        };
+        (self.include.is_empty() || self.include.contains(&name)) && !self.exclude.contains(&name)
+    }
+}
+
+/// Decides which file paths should be dropped from the log
+pub struct PathFilter {
+    /// The --sensible-defaults patterns
+    pub exclude: Option<GlobSet>,
+    /// Files that don't match any of these are left out
+    pub path_include: Option<GlobSet>,
+    pub path_exclude: Option<GlobSet>,
+}
+
+impl PathFilter {
+    /// Why a file is left out, if it is. `file` is its path in the log,
+    /// a file matching both --path-exclude and --path-include is left out
+    pub fn reason(&self, file: &str) -> Option<FilterReason> {
+        if self
+            .path_exclude
+            .as_ref()
+            .is_some_and(|set| set.is_match(file))
+        {
+            Some(FilterReason::PathExclude)
+        } else if self
+            .path_include
+            .as_ref()
+            .is_some_and(|set| !set.is_match(file))
+        {
+            Some(FilterReason::PathInclude)
+        } else if self.exclude.as_ref().is_some_and(|set| set.is_match(file)) {
+            Some(FilterReason::SensibleDefaults)
+        } else {
+            None
+        }
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
+// Reason: These are independent options, not a state machine
 pub struct GourceLogConfig {
     pub output_file: Option<String>,
     pub aliases: HashMap<String, String>,
+    pub alias_patterns: Vec<AliasPattern>,
     pub merge_sort_config: Option<MergeSortConfig>,
+    pub avatar_dir: Option<PathBuf>,
+    /// Flush the output and write a checkpoint after this many records
+    pub checkpoint_every: Option<NonZeroU64>,
+    /// Carry on from the last checkpoint instead of writing the output from the start
+    pub resume: bool,
+    /// Comment lines to write at the top of the output, without the leading '#'
+    pub header: Vec<String>,
+    /// Write one log per author into this directory instead of a single log
+    pub split_by_author: Option<PathBuf>,
+    /// Write the per-author logs into this tar.gz archive instead of the directory
+    pub archive: Option<PathBuf>,
+    /// Also write every record as JSON to this file
+    pub json_output: Option<PathBuf>,
+    /// Add the date and time to every record
+    pub debug_dates: bool,
+    /// Match aliases ignoring case and write every casing of a name the same way
+    pub case_insensitive_authors: bool,
+    /// Write the log in Gource's format or as JSON
+    pub format: OutputFormat,
+    /// The --compress setting, without it the output is compressed when its path ends in `.gz`
+    pub compress: Option<OutputCompression>,
+    /// The order of the columns in the Gource log
+    pub field_order: Vec<LogField>,
+    /// What the colour column is based on
+    pub color_by: ColorBy,
+    pub max_events_per_dir: Option<NonZeroU64>,
+    /// Count the records in the temporary file up front so the progress bar knows how long it is
+    pub progress_count: bool,
+    pub dir_level: DirectoryLevel,
+    pub stats: Option<StatsConfig>,
+    pub activity: Option<ActivityConfig>,
+    pub flatten_single_child_dirs: bool,
+}
+
+/// What counts as a directory for `--max-events-per-dir`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DirectoryLevel {
+    /// The first component of the path, e.g. `repo` for `repo/src/main.rs`
+    Top,
+    /// The directory containing the file, e.g. `repo/src` for `repo/src/main.rs`
+    Full,
+}
+
+/// A pattern from --alias-regex, a username it matches is replaced as a whole
+#[derive(Clone)]
+pub struct AliasPattern {
+    pub pattern: Regex,
+    /// `$1` and `${name}` are replaced by what the pattern's groups matched
+    pub replacement: String,
+}
+
+impl AliasPattern {
+    /// The name given by the first pattern that matches the username, if any of them do
+    pub fn resolve(patterns: &[Self], username: &str) -> Option<String> {
+        patterns.iter().find_map(|alias| {
+            let captures = alias.pattern.captures(username)?;
+            let mut name = String::new();
+            captures.expand(&alias.replacement, &mut name);
+            Some(name)
+        })
+    }
+}
+
+/// Applies the global aliases as the log is written, then the --alias-regex patterns to names without one.
+/// With --case-insensitive-authors every casing of a name is written the way it was first seen,
+/// the log is sorted so that's its earliest commit
+pub struct AuthorNames<'a> {
+    aliases: &'a HashMap<String, String>,
+    patterns: &'a [AliasPattern],
+    /// The first casing of each lowercase name, only with --case-insensitive-authors
+    display: Option<HashMap<String, String>>,
+}
+
+impl<'a> AuthorNames<'a> {
+    pub fn new(
+        aliases: &'a HashMap<String, String>,
+        patterns: &'a [AliasPattern],
+        case_insensitive: bool,
+    ) -> Self {
+        Self {
+            aliases,
+            patterns,
+            display: case_insensitive.then(HashMap::new),
+        }
+    }
+
+    pub fn resolve(&mut self, username: &mut String) {
+        let Some(display) = &mut self.display else {
+            if let Some(alias) = self
+                .aliases
+                .get(username.as_str())
+                .cloned()
+                .or_else(|| AliasPattern::resolve(self.patterns, username))
+            {
+                *username = alias;
+            }
+            return;
+        };
+
+        if let Some(alias) = self
+            .aliases
+            .get(&username.to_lowercase())
+            .cloned()
+            .or_else(|| AliasPattern::resolve(self.patterns, username))
+        {
+            *username = alias;
+        }
+        let key = username.to_lowercase();
+        if let Some(first) = display.get(&key) {
+            username.clone_from(first);
+        } else {
+            display.insert(key, username.clone());
+        }
+    }
+}
+
+/// Keeps the first N events in each directory of a time sorted log
+pub struct DirectoryCap {
+    max_events: u64,
+    level: DirectoryLevel,
+    counts: HashMap<String, u64>,
+}
+
+impl DirectoryCap {
+    pub fn new(max_events: NonZeroU64, level: DirectoryLevel) -> Self {
+        Self {
+            max_events: max_events.get(),
+            level,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Count an event for the file's directory, false once the directory is full
+    pub fn allows(&mut self, file: &str) -> bool {
+        // Files in the root of the tree share the empty directory
+        let directory = match self.level {
+            DirectoryLevel::Top => file.split_once('/').map_or("", |(top, _)| top),
+            DirectoryLevel::Full => file.rsplit_once('/').map_or("", |(dir, _)| dir),
+        };
+        // Avoid allocating a key for every event in a directory we've already seen
+        let count = if let Some(count) = self.counts.get_mut(directory) {
+            count
+        } else {
+            self.counts.entry(directory.to_string()).or_default()
+        };
+        if *count >= self.max_events {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+/// Every directory that appears anywhere in the log, for `--flatten-single-child-dirs`
+#[derive(Default)]
+pub struct DirectoryTree {
+    directories: HashMap<String, DirectoryEntries>,
+}
+
+#[derive(Default)]
+struct DirectoryEntries {
+    directories: HashSet<String>,
+    has_files: bool,
+}
+
+impl DirectoryTree {
+    pub fn add(&mut self, file: &str) {
+        let mut parent = String::new();
+        let mut components = file.split('/').peekable();
+        while let Some(component) = components.next() {
+            let entries = self.directories.entry(parent.clone()).or_default();
+            if components.peek().is_none() {
+                entries.has_files = true;
+                break;
+            }
+            if !entries.directories.contains(component) {
+                entries.directories.insert(component.to_string());
+            }
+            if !parent.is_empty() {
+                parent.push('/');
+            }
+            parent.push_str(component);
+        }
+    }
+
+    /// Join each directory that only ever contains a single directory to its child with a dot,
+    /// e.g. `src/com/example/App.java` becomes `src/com.example/App.java` when `com` only contains `example`
+    pub fn flatten(&self, file: &str) -> String {
+        let Some((directories, name)) = file.rsplit_once('/') else {
+            return file.to_string();
+        };
+        let mut flattened = String::with_capacity(file.len());
+        let mut path = String::with_capacity(directories.len());
+        for directory in directories.split('/') {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(directory);
+            flattened.push_str(directory);
+            // The file is somewhere below the only child, so there's always another directory to join
+            let single_child = self
+                .directories
+                .get(&path)
+                .is_some_and(|entries| !entries.has_files && entries.directories.len() == 1);
+            flattened.push(if single_child { '.' } else { '/' });
+        }
+        flattened.push_str(name);
+        flattened
+    }
 }
 
 pub struct MergeSortConfig {
     pub chunk_size: u64,
     pub tmp_location: PathBuf,
+    /// The records to sort, `rource-temp.bin` in `tmp_location` unless --append-temp or --finalize name one
+    pub temp_file: PathBuf,
+    /// Size the chunks by record count so the sort never writes more than this many files
+    pub max_temp_files: Option<NonZeroUsize>,
 }
 
 impl MergeSortConfig {
@@ -150,7 +1165,216 @@ impl MergeSortConfig {
 
         Ok(Self {
             chunk_size,
+            temp_file: tmp_location.join(TEMPORARY_LOG_FILENAME),
             tmp_location,
+            max_temp_files: None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{lines, read, Fixture};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn author_filter_with_only_an_include_list() {
+        let filter = AuthorFilter::new(&names(&["Ann", "Bob"]), &[], false);
+        assert!(filter.allows("Ann"));
+        assert!(filter.allows("Bob"));
+        assert!(!filter.allows("Cat"));
+        assert!(!filter.allows("ann"));
+
+        let filter = AuthorFilter::new(&names(&["Ann"]), &[], true);
+        assert!(filter.allows("ANN"));
+        assert!(!filter.allows("Bob"));
+    }
+
+    #[test]
+    fn author_filter_with_only_an_exclude_list() {
+        let filter = AuthorFilter::new(&[], &names(&["ci-bot"]), false);
+        assert!(filter.allows("Ann"));
+        assert!(!filter.allows("ci-bot"));
+        assert!(filter.allows("CI-Bot"));
+
+        let filter = AuthorFilter::new(&[], &names(&["ci-bot"]), true);
+        assert!(!filter.allows("CI-Bot"));
+        assert!(AuthorFilter::new(&[], &[], false).allows("Anyone"));
+    }
+
+    #[test]
+    fn author_filter_excludes_a_name_in_both_lists() {
+        let filter = AuthorFilter::new(&names(&["Ann", "Bob"]), &names(&["bob"]), true);
+        assert!(filter.allows("Ann"));
+        assert!(!filter.allows("Bob"));
+        assert!(!filter.allows("Cat"));
+    }
+
+    #[test]
+    fn author_filter_leaves_out_whole_commits() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit("Ann", 100, &[("a.txt", Some("one"))])?;
+        fixture.commit("Bob", 200, &[("b.txt", Some("two"))])?;
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(&fixture.path(), &["--backend", backend, "--author", "Bob"])?;
+            assert_eq!(lines(&logs), ["200|Bob|A|b.txt"], "{backend}");
+            let logs = read(
+                &fixture.path(),
+                &[
+                    "--backend",
+                    backend,
+                    "--exclude-author",
+                    "bob",
+                    "--author-ignore-case",
+                ],
+            )?;
+            assert_eq!(lines(&logs), ["100|Ann|A|a.txt"], "{backend}");
+        }
+        Ok(())
+    }
+
+    fn path_filter(include: &[&str], exclude: &[&str]) -> anyhow::Result<PathFilter> {
+        crate::validation::validate_path_filters(false, &[], &names(include), &names(exclude))
+    }
+
+    #[test]
+    fn path_filter_with_only_include_patterns() -> anyhow::Result<()> {
+        let filter = path_filter(&["src/**"], &[])?;
+        assert_eq!(filter.reason("src/main.rs"), None);
+        assert_eq!(
+            filter.reason("docs/readme.md"),
+            Some(FilterReason::PathInclude)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn path_filter_with_only_exclude_patterns() -> anyhow::Result<()> {
+        let filter = path_filter(&[], &["**/*.lock"])?;
+        assert_eq!(filter.reason("Cargo.lock"), Some(FilterReason::PathExclude));
+        assert_eq!(filter.reason("src/main.rs"), None);
+        assert_eq!(path_filter(&[], &[])?.reason("anything"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn path_filter_excludes_a_path_matching_both() -> anyhow::Result<()> {
+        let filter = path_filter(&["src/**"], &["src/generated/**"])?;
+        assert_eq!(filter.reason("src/main.rs"), None);
+        assert_eq!(
+            filter.reason("src/generated/api.rs"),
+            Some(FilterReason::PathExclude)
+        );
+        assert_eq!(filter.reason("build.rs"), Some(FilterReason::PathInclude));
+        Ok(())
+    }
+
+    #[test]
+    fn line_breaks_in_file_names_are_escaped() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit("Ann", 100, &[("two\nlines\r.txt", Some("one"))])?;
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(&fixture.path(), &["--backend", backend])?;
+            assert_eq!(lines(&logs), ["100|Ann|A|two\\nlines\\r.txt"], "{backend}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn owner_map_uses_the_longest_matching_directory() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit(
+            "Ann",
+            100,
+            &[
+                ("README.md", Some("one")),
+                ("src/main.rs", Some("two")),
+                ("src/api/routes.rs", Some("three")),
+                ("srcs/other.rs", Some("four")),
+            ],
+        )?;
+        let map = tempfile::NamedTempFile::new()?;
+        std::fs::write(
+            map.path(),
+            "# Owners\nsrc::Core Team\n\nsrc/api/::API Team\n",
+        )?;
+        let map = map.path().to_string_lossy();
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(
+                &fixture.path(),
+                &["--backend", backend, "--owner-map", &map],
+            )?;
+            assert_eq!(
+                lines(&logs),
+                [
+                    "100|Ann|A|README.md",
+                    "100|API Team|A|src/api/routes.rs",
+                    "100|Core Team|A|src/main.rs",
+                    "100|Ann|A|srcs/other.rs",
+                ],
+                "{backend}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn summarized_root_commits_are_filtered_on_the_real_paths() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        fixture.commit(
+            "Ann",
+            100,
+            &[
+                ("node_modules/x.js", Some("one")),
+                ("src/a.rs", Some("two")),
+                ("src/b.rs", Some("three")),
+                ("docs/readme.md", Some("four")),
+            ],
+        )?;
+        for backend in ["libgit2", "git-cli"] {
+            let summarize = ["--backend", backend, "--root-commit-mode", "summarize"];
+            let logs = read(
+                &fixture.path(),
+                &[&summarize[..], &["--sensible-defaults"]].concat(),
+            )?;
+            assert_eq!(
+                lines(&logs),
+                ["100|Ann|A|docs", "100|Ann|A|src"],
+                "{backend}"
+            );
+            let logs = read(
+                &fixture.path(),
+                &[&summarize[..], &["--path-include", "src/**"]].concat(),
+            )?;
+            assert_eq!(lines(&logs), ["100|Ann|A|src"], "{backend}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn date_source_picks_the_author_or_committer_date() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        // Written at 100, cherry-picked at 500
+        let id = fixture.commit_as(("Ann", 100), ("Bob", 500), &[("a.txt", Some("one"))])?;
+        let repo = Repository::open(fixture.path())?;
+        let commit = repo.find_commit(id)?;
+        assert_eq!(DateSource::Author.time(&commit), 100);
+        assert_eq!(DateSource::Committer.time(&commit), 500);
+
+        for backend in ["libgit2", "git-cli"] {
+            for (args, expected) in [
+                (&[][..], "500|Ann|A|a.txt"),
+                (&["--date-source", "committer"][..], "500|Ann|A|a.txt"),
+                (&["--date-source", "author"][..], "100|Ann|A|a.txt"),
+            ] {
+                let logs = read(&fixture.path(), &[&["--backend", backend], args].concat())?;
+                assert_eq!(lines(&logs), [expected], "{backend} {args:?}");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Repositories for the tests, built with libgit2 in a temporary directory
+
+use crate::cli::ClapArguments;
+use crate::progress::{Progress, ProgressStyles};
+use crate::store::LogStore;
+use crate::structs::{GitLogConfig, GourceLogConfig, GourceLogFormat};
+use clap::Parser;
+use git2::{IndexAddOption, Oid, Repository, Signature, Time};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// A repository on `main` whose commits are made by the test, removed when it's dropped
+pub struct Fixture {
+    dir: TempDir,
+    repo: Repository,
+}
+
+impl Fixture {
+    pub fn new() -> anyhow::Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("rource-fixture")
+            .tempdir()?;
+        let repo = Repository::init(dir.path())?;
+        repo.set_head("refs/heads/main")?;
+        Ok(Self { dir, repo })
+    }
+
+    /// Where the working tree is, canonicalised the same way --path is
+    pub fn path(&self) -> PathBuf {
+        self.dir
+            .path()
+            .canonicalize()
+            .unwrap_or_else(|_| self.dir.path().to_path_buf())
+    }
+
+    /// Commit changes to the checked out branch, each one writes a file or with `None` removes it
+    pub fn commit(
+        &self,
+        author: &str,
+        time: i64,
+        changes: &[(&str, Option<&str>)],
+    ) -> anyhow::Result<Oid> {
+        self.commit_as((author, time), (author, time), changes)
+    }
+
+    /// Commit with a different author and committer, each given as a name and a time
+    pub fn commit_as(
+        &self,
+        author: (&str, i64),
+        committer: (&str, i64),
+        changes: &[(&str, Option<&str>)],
+    ) -> anyhow::Result<Oid> {
+        let mut index = self.repo.index()?;
+        for (path, contents) in changes {
+            let file = self.dir.path().join(path);
+            if let Some(contents) = contents {
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&file, contents)?;
+                index.add_path(Path::new(path))?;
+            } else {
+                fs::remove_file(&file)?;
+                index.remove_path(Path::new(path))?;
+            }
+        }
+        index.write()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let parents = self.head()?.into_iter().collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        Ok(self.repo.commit(
+            Some("HEAD"),
+            &signature(author.0, author.1)?,
+            &signature(committer.0, committer.1)?,
+            "Change",
+            &tree,
+            &parents,
+        )?)
+    }
+
+    /// Start a branch at the checked out commit, without switching to it
+    pub fn branch(&self, name: &str) -> anyhow::Result<()> {
+        let head = self
+            .head()?
+            .ok_or_else(|| anyhow::anyhow!("Nothing to branch from"))?;
+        self.repo.branch(name, &head, false)?;
+        Ok(())
+    }
+
+    /// Check out a branch, replacing the working tree and index
+    pub fn checkout(&self, name: &str) -> anyhow::Result<()> {
+        self.repo.set_head(&format!("refs/heads/{name}"))?;
+        self.repo
+            .checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+        Ok(())
+    }
+
+    /// Merge a branch into the checked out one with a merge commit, the branches mustn't conflict
+    pub fn merge(&self, author: &str, time: i64, branch: &str) -> anyhow::Result<Oid> {
+        let ours = self
+            .head()?
+            .ok_or_else(|| anyhow::anyhow!("Nothing to merge into"))?;
+        let theirs = self
+            .repo
+            .find_branch(branch, git2::BranchType::Local)?
+            .get()
+            .peel_to_commit()?;
+        let mut merged = self.repo.merge_commits(&ours, &theirs, None)?;
+        if merged.has_conflicts() {
+            anyhow::bail!("{branch} conflicts");
+        }
+        let tree = self.repo.find_tree(merged.write_tree_to(&self.repo)?)?;
+        let signature = signature(author, time)?;
+        let id = self.repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "Merge",
+            &tree,
+            &[&ours, &theirs],
+        )?;
+        self.repo
+            .checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+        // Keep the index in step with the merge for the next commit
+        let mut index = self.repo.index()?;
+        index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+        index.write()?;
+        Ok(id)
+    }
+
+    pub const fn repo(&self) -> &Repository {
+        &self.repo
+    }
+
+    fn head(&self) -> anyhow::Result<Option<git2::Commit<'_>>> {
+        match self.repo.head() {
+            Ok(head) => Ok(Some(head.peel_to_commit()?)),
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// `main` with a commit of its own, and a `feature` branch with a commit only it has
+pub fn two_branches() -> anyhow::Result<Fixture> {
+    let fixture = Fixture::new()?;
+    fixture.commit("Ann", 100, &[("a.txt", Some("one"))])?;
+    fixture.branch("feature")?;
+    fixture.checkout("feature")?;
+    fixture.commit("Bob", 200, &[("feature.txt", Some("two"))])?;
+    fixture.checkout("main")?;
+    fixture.commit("Ann", 300, &[("a.txt", Some("three"))])?;
+    Ok(fixture)
+}
+
+fn signature(name: &str, time: i64) -> anyhow::Result<Signature<'static>> {
+    Ok(Signature::new(
+        name,
+        &format!("{}@example.com", name.to_lowercase()),
+        &Time::new(time, 0),
+    )?)
+}
+
+/// The arguments a run over a repository would have, after `rource --path <path>`
+pub fn arguments(path: &Path, args: &[&str]) -> anyhow::Result<ClapArguments> {
+    let path = path.to_string_lossy();
+    Ok(ClapArguments::try_parse_from(
+        ["rource", "--path", &path].iter().chain(args),
+    )?)
+}
+
+/// The settings repositories are read with for these arguments
+pub fn config(path: &Path, args: &[&str]) -> anyhow::Result<GitLogConfig> {
+    crate::build_git_log_config(&arguments(path, args)?, &HashMap::new(), None)
+}
+
+/// The settings the log is written with for these arguments, without aliases or a header
+pub fn gource_config(path: &Path, args: &[&str]) -> anyhow::Result<GourceLogConfig> {
+    Ok(crate::build_gource_log_config(
+        &arguments(path, args)?,
+        HashMap::new(),
+        Vec::new(),
+        Vec::new(),
+        None,
+    ))
+}
+
+/// Read a single repository the way a run with these arguments would, sorted like the log is
+pub fn read(path: &Path, args: &[&str]) -> anyhow::Result<Vec<GourceLogFormat>> {
+    let config = config(path, args)?;
+    let store = LogStore::in_memory();
+    let progress = Progress::new(ProgressStyles::new(None, None)?);
+    progress.hide();
+    crate::git_stuff::read_repositories(path, &[path.to_path_buf()], &store, &progress, &config)?;
+    let mut logs = store.finish()?;
+    logs.sort();
+    Ok(logs)
+}
+
+/// The records as `timestamp|username|type|file`, for comparing with what's expected
+pub fn lines(logs: &[GourceLogFormat]) -> Vec<String> {
+    logs.iter()
+        .map(|log| {
+            format!(
+                "{}|{}|{:?}|{}",
+                log.timestamp, log.username, log.r#type, log.file
+            )
+        })
+        .collect()
+}
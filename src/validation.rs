@@ -1,16 +1,36 @@
-use anyhow::anyhow;
+use crate::consts::SENSIBLE_DEFAULT_EXCLUDES;
+use crate::git_stuff::start_commit;
+use crate::output::{LogField, OutputCompression};
+use crate::structs::{
+    AliasPattern, GitLogConfig, GourceLogFormat, OwnerMap, PathFilter, RepositoryOptions,
+    RepositoryOrder, StartPoint,
+};
+use crate::warnings::{record, Warning};
+use anyhow::{anyhow, bail, Context};
+use flate2::read::GzDecoder;
 use git2::Repository;
-use log::{error, warn};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use indicatif::ProgressBar;
+use log::{info, warn};
+use regex::RegexBuilder;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::{fs, io};
 
-pub fn validate_aliases(aliases: &[String]) -> anyhow::Result<HashMap<String, String>> {
-    let mut validated_aliases: HashMap<String, String> = HashMap::with_capacity(aliases.len());
-    for alias in aliases {
+/// Check each alias along with where it came from, e.g. `on line 3 of aliases.txt`.
+/// When a username is aliased more than once the last alias wins
+pub fn validate_aliases<'a>(
+    aliases: impl IntoIterator<Item = (&'a str, String)>,
+) -> anyhow::Result<HashMap<String, String>> {
+    let mut validated_aliases: HashMap<String, String> = HashMap::new();
+    for (alias, origin) in aliases {
         let parts = alias.split("::").collect::<Vec<_>>();
         if parts.len() != 2 {
             return Err(anyhow!(
-                "Invalid alias format, expected <GIT_USERNAME>::<GOURCE_USERNAME>"
+                "Invalid alias format {alias:?} {origin}, expected <GIT_USERNAME>::<GOURCE_USERNAME>"
             ));
         }
         validated_aliases.insert(parts[0].to_string(), parts[1].to_string());
@@ -18,15 +38,433 @@ pub fn validate_aliases(aliases: &[String]) -> anyhow::Result<HashMap<String, St
     Ok(validated_aliases)
 }
 
-/// Try to find potential git repositories in a directory
+/// Compile the --alias-regex patterns in the order they were given, with --case-insensitive-authors they ignore case
+pub fn validate_alias_patterns(
+    aliases: &[String],
+    case_insensitive: bool,
+) -> anyhow::Result<Vec<AliasPattern>> {
+    aliases
+        .iter()
+        .map(|alias| {
+            // Patterns are more likely to contain a :: than names
+            let (pattern, replacement) = alias.rsplit_once("::").ok_or_else(|| {
+                anyhow!("Invalid --alias-regex {alias:?}, expected <PATTERN>::<REPLACEMENT>")
+            })?;
+            let pattern = RegexBuilder::new(pattern)
+                .case_insensitive(case_insensitive)
+                .build()
+                .map_err(|e| anyhow!("Invalid --alias-regex pattern {pattern:?}: {e}"))?;
+            Ok(AliasPattern {
+                pattern,
+                replacement: replacement.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Read the `<USERNAME>::<REPLACEMENT>` lines from an alias file, each with the line it came from
+pub fn read_alias_file(path: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read alias file {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (number, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            (
+                line.to_string(),
+                format!("on line {} of {}", number + 1, path.display()),
+            )
+        })
+        .collect())
+}
+
+/// Read the `<DIRECTORY>::<OWNER>` lines from an owner map file
+pub fn validate_owner_map(path: &Path) -> anyhow::Result<OwnerMap> {
+    let contents =
+        fs::read_to_string(path).context(format!("Failed to read owner map {}", path.display()))?;
+
+    let mut owners = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((prefix, owner)) = line.split_once("::") else {
+            bail!(
+                "Invalid owner map format on line {} of {}, expected <DIRECTORY>::<OWNER>",
+                number + 1,
+                path.display()
+            );
+        };
+        owners.push((
+            prefix.trim().trim_matches('/').to_string(),
+            owner.trim().replace('|', "#"),
+        ));
+    }
+    Ok(OwnerMap::new(owners))
+}
+
+/// Collect the commit ids given with --exclude-commit and in --exclude-commits-file,
+/// each must be at least 4 hex digits. Blank lines and lines starting with '#' in the file are ignored
+pub fn validate_commit_ids(ids: &[String], file: Option<&Path>) -> anyhow::Result<Vec<String>> {
+    let mut validated = Vec::with_capacity(ids.len());
+    for id in ids {
+        validated
+            .push(validate_commit_id(id).map_err(|e| anyhow!("Invalid --exclude-commit: {e}"))?);
+    }
+    if let Some(path) = file {
+        let contents = fs::read_to_string(path)
+            .context(format!("Failed to read commits file {}", path.display()))?;
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            validated.push(validate_commit_id(line).map_err(|e| {
+                anyhow!(
+                    "Invalid commit on line {} of {}: {e}",
+                    number + 1,
+                    path.display()
+                )
+            })?);
+        }
+    }
+    Ok(validated)
+}
+
+fn validate_commit_id(id: &str) -> anyhow::Result<String> {
+    if !(4..=40).contains(&id.len()) || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("'{id}' is not a commit id, expected 4 to 40 hex digits");
+    }
+    Ok(id.to_lowercase())
+}
+
+/// Parse the commit given with --since-commit or --until-commit, a full or abbreviated SHA
+pub fn parse_commit_sha(sha: &str) -> Result<String, String> {
+    if !(7..=40).contains(&sha.len()) || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "'{sha}' is not a commit SHA, expected 7 to 40 hex digits"
+        ));
+    }
+    Ok(sha.to_lowercase())
+}
+
+/// Check that every column of the log is named exactly once
+pub fn validate_field_order(fields: &[LogField]) -> anyhow::Result<()> {
+    for field in LogField::GOURCE_ORDER {
+        match fields.iter().filter(|f| **f == field).count() {
+            0 => bail!("--field-order is missing {}", field.name()),
+            1 => {}
+            _ => bail!("--field-order lists {} more than once", field.name()),
+        }
+    }
+    Ok(())
+}
+
+/// Check that --since isn't after --until
+pub fn validate_date_range(since: Option<i64>, until: Option<i64>) -> anyhow::Result<()> {
+    if let (Some(since), Some(until)) = (since, until) {
+        if since > until {
+            bail!(
+                "--since {} is after --until {}",
+                format_date(since),
+                format_date(until)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Build the path filter from --path-include, --path-exclude and the built-in defaults,
+/// minus any the user asked to keep
+pub fn validate_path_filters(
+    sensible_defaults: bool,
+    sensible_defaults_skip: &[String],
+    path_include: &[String],
+    path_exclude: &[String],
+) -> anyhow::Result<PathFilter> {
+    for skip in sensible_defaults_skip {
+        if !SENSIBLE_DEFAULT_EXCLUDES.contains(&skip.as_str()) {
+            bail!(
+                "'{skip}' is not one of the sensible default excludes: {}",
+                SENSIBLE_DEFAULT_EXCLUDES.join(", ")
+            );
+        }
+    }
+
+    let mut patterns: Vec<&str> = Vec::new();
+    if sensible_defaults {
+        patterns.extend(
+            SENSIBLE_DEFAULT_EXCLUDES
+                .iter()
+                .filter(|p| !sensible_defaults_skip.iter().any(|s| s == *p)),
+        );
+    }
+
+    let exclude = if patterns.is_empty() {
+        None
+    } else {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(Glob::new(pattern)?);
+        }
+        Some(builder.build()?)
+    };
+    Ok(PathFilter {
+        exclude,
+        path_include: glob_filter("--path-include", path_include)?,
+        path_exclude: glob_filter("--path-exclude", path_exclude)?,
+    })
+}
+
+/// Build a filter matching the files tracked by Git LFS from the contents of a `.gitattributes` file,
+/// `None` if nothing is tracked by LFS
+pub fn lfs_filter(gitattributes: &str) -> anyhow::Result<Option<GlobSet>> {
+    let mut builder = GlobSetBuilder::new();
+    let mut empty = true;
+    for line in gitattributes.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(pattern) = fields.next().filter(|p| !p.starts_with('#')) else {
+            continue;
+        };
+        if !fields.any(|attribute| attribute == "filter=lfs") {
+            continue;
+        }
+
+        let pattern = repository_pattern(pattern);
+        match GlobBuilder::new(&pattern).literal_separator(true).build() {
+            Ok(glob) => {
+                builder.add(glob);
+                empty = false;
+            }
+            Err(e) => warn!("Ignoring LFS pattern {pattern:?} from .gitattributes: {e}"),
+        }
+    }
+
+    if empty {
+        return Ok(None);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// Like .gitignore, patterns without a slash match in any directory,
+/// everything else is relative to the root of the repository
+fn repository_pattern(pattern: &str) -> String {
+    if pattern.contains('/') {
+        pattern.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{pattern}")
+    }
+}
+
+/// Read the `[rource]` section of each repository's own git config, for --use-git-config.
+/// Options from the job and aliases from the command line take precedence
+pub fn read_git_config(
+    repositories: &[PathBuf],
+    options: &mut HashMap<PathBuf, RepositoryOptions>,
+    aliases: &HashMap<String, String>,
+) -> anyhow::Result<()> {
+    for path in repositories {
+        let Ok(config) = Repository::open(path)
+            .and_then(|r| r.config())
+            .and_then(|c| c.open_level(git2::ConfigLevel::Local))
+        else {
+            continue;
+        };
+        let context = || format!("Invalid [rource] git config in {}", path.display());
+        let options = options.entry(path.clone()).or_default();
+
+        if options.prefix.is_none() {
+            options.prefix = config
+                .get_string("rource.prefix")
+                .ok()
+                .map(|prefix| prefix.trim_matches('/').to_string())
+                .filter(|prefix| !prefix.is_empty());
+        }
+
+        let repository_aliases = validate_aliases(
+            config_values(&config, "rource.alias")?
+                .iter()
+                .map(|alias| (alias.as_str(), "in rource.alias".to_string())),
+        )
+        .with_context(context)?;
+        for (from, to) in repository_aliases {
+            if !aliases.contains_key(&from) {
+                options.aliases.entry(from).or_insert(to);
+            }
+        }
+
+        let excludes = config_values(&config, "rource.exclude")?;
+        if !excludes.is_empty() {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in &excludes {
+                builder.add(
+                    GlobBuilder::new(&repository_pattern(pattern))
+                        .literal_separator(true)
+                        .build()
+                        .with_context(context)?,
+                );
+            }
+            options.excludes = Some(Arc::new(builder.build()?));
+        }
+    }
+    Ok(())
+}
+
+/// Every value of a multi-valued git config key
+fn config_values(config: &git2::Config, name: &str) -> anyhow::Result<Vec<String>> {
+    let mut values = Vec::new();
+    config.multivar(name, None)?.for_each(|entry| {
+        if let Some(value) = entry.value() {
+            values.push(value.to_string());
+        }
+    })?;
+    Ok(values)
+}
+
+/// Make sure every repository is somewhere under the base path that file paths are relative to
+pub fn validate_relative_base(base: &Path, repositories: &[PathBuf]) -> anyhow::Result<()> {
+    for repository in repositories {
+        if !repository.starts_with(base) {
+            bail!(
+                "Repository {} is not under the relative path base {}",
+                repository.display(),
+                base.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Read a finished log back and check it's in the order Gource expects,
+/// aliases are applied after sorting so usernames aren't part of the check
+pub fn verify_sort(
+    output: &Path,
+    compression: OutputCompression,
+    field_order: &[LogField],
+    preserve_commit_order: bool,
+) -> anyhow::Result<()> {
+    let file = fs::File::open(output).context(format!("Failed to open {}", output.display()))?;
+    let input: Box<dyn io::Read> = match compression {
+        OutputCompression::None => Box::new(file),
+        OutputCompression::Gzip => Box::new(GzDecoder::new(file)),
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(b'|')
+        .comment(Some(b'#'))
+        .from_reader(input);
+    // The log has no column names, name them in the --field-order so each is read from the right place
+    let headers = field_order
+        .iter()
+        .map(|field| field.name())
+        .collect::<csv::StringRecord>();
+
+    let mut previous: Option<GourceLogFormat> = None;
+    let mut count = 0_u64;
+    for (index, record) in reader.records().enumerate() {
+        let record = record
+            .and_then(|record| record.deserialize::<GourceLogFormat>(Some(&headers)))
+            .map_err(|e| {
+                anyhow!(
+                    "Failed to read line {} of {}: {e}",
+                    index + 1,
+                    output.display()
+                )
+            })?;
+        if let Some(previous) = &previous {
+            let order = previous.timestamp.cmp(&record.timestamp);
+            // The commit order within each second isn't in the output
+            let order = if preserve_commit_order {
+                order
+            } else {
+                order
+                    .then(previous.file.cmp(&record.file))
+                    .then(previous.r#type.cmp(&record.r#type))
+            };
+            if order == Ordering::Greater {
+                bail!(
+                    "Output is not sorted, line {} ({}|{:?}|{}) comes after ({}|{:?}|{})",
+                    index + 1,
+                    record.timestamp,
+                    record.r#type,
+                    record.file,
+                    previous.timestamp,
+                    previous.r#type,
+                    previous.file
+                );
+            }
+        }
+        previous = Some(record);
+        count += 1;
+    }
+
+    info!(
+        "Verified the sort order of {count} records in {}",
+        output.display()
+    );
+    Ok(())
+}
+
+/// Compile the patterns given to an option like --include or --path-exclude, each one a glob like `payments-*`
+/// or just a name. `None` when there aren't any, so everything is included and nothing is excluded
+pub fn glob_filter(option: &str, patterns: &[String]) -> anyhow::Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).context(format!("Invalid {option} pattern {pattern:?}"))?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// Try to find potential git repositories in a directory,
+/// the spinner counts the directories scanned and repositories found so far.
+/// With `recursive` subdirectories are searched down to `max_depth` levels below the root, or without limit
 pub fn discover_repositories(
     root: &Path,
     recursive: bool,
-    include: &[String],
-    exclude: &[String],
+    max_depth: Option<usize>,
+    include: Option<&GlobSet>,
+    exclude: Option<&GlobSet>,
+    spinner: &ProgressBar,
 ) -> anyhow::Result<Vec<PathBuf>> {
     let mut repositories = Vec::new();
+    scan_directory(
+        root,
+        if recursive { max_depth } else { Some(0) },
+        include,
+        exclude,
+        spinner,
+        &mut repositories,
+    )?;
+    Ok(repositories)
+}
 
+/// `depth` is how many more levels of subdirectories to search, `None` for no limit
+fn scan_directory(
+    root: &Path,
+    depth: Option<usize>,
+    include: Option<&GlobSet>,
+    exclude: Option<&GlobSet>,
+    spinner: &ProgressBar,
+    repositories: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    spinner.inc(1);
+    spinner.set_message(format!(
+        "{} directories scanned, {} repositories found",
+        spinner.position(),
+        repositories.len()
+    ));
+    if is_bare_repository(root) {
+        // There's no .git directory, the repository is the directory itself
+        repositories.push(root.to_path_buf());
+        return Ok(());
+    }
     for entry in root.read_dir()?.collect::<Result<Vec<_>, _>>()? {
         if !entry.file_type()?.is_dir() {
             // Skip non-directories
@@ -39,65 +477,305 @@ pub fn discover_repositories(
             .ok_or_else(|| anyhow!("Unable to read path {:?}", entry))?
             .to_string();
 
+        // Is this potentially a git repository? The repository's own directory
+        // has already passed the include/exclude checks to get here
+        if entry_name == ".git" {
+            // Push this as a potential repository
+            repositories.push(root.to_path_buf());
+
+            // Don't recurse into .git directories
+            continue;
+        }
+
         // Assuming we're at the parent level before we recurse, check if we should skip this directory
-        if !exclude.is_empty() && exclude.contains(&entry_name) {
+        if exclude.is_some_and(|exclude| exclude.is_match(&entry_name)) {
             // Skip excluded directories
             continue;
         }
-        if !include.is_empty() && !include.contains(&entry_name) {
+        if include.is_some_and(|include| !include.is_match(&entry_name)) {
             // Skip non-included directories
             continue;
         }
 
-        // After passing the include/exclude checks,
-        // Is this potentially a git repository?
-        if entry_name == ".git" {
-            // Push this as a potential repository
-            repositories.push(root.to_path_buf());
-
-            // Don't recurse into .git directories
-            continue;
+        if depth != Some(0) {
+            scan_directory(
+                &entry.path(),
+                depth.map(|depth| depth - 1),
+                include,
+                exclude,
+                spinner,
+                repositories,
+            )?;
         }
+    }
+
+    Ok(())
+}
+
+/// Does the directory look like a bare repository, with `HEAD`, `objects/` and `refs/` at the top
+fn is_bare_repository(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
 
-        if recursive {
-            let mut sub_repositories =
-                discover_repositories(&entry.path(), recursive, include, exclude)?;
-            repositories.append(&mut sub_repositories);
+/// Drop repositories whose HEAD commit is older than the cutoff, given in unix time.
+/// With --default-branch it's the default branch's latest commit that counts
+pub fn filter_inactive_repositories(
+    mut repositories: Vec<PathBuf>,
+    since: i64,
+    config: &GitLogConfig,
+) -> Vec<PathBuf> {
+    let before = repositories.len();
+    repositories.retain(|path| {
+        Repository::open(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|r| Ok(start_commit(&r, config)?.time().seconds()))
+            .map_or(true, |time| time >= since)
+    });
+    let skipped = before - repositories.len();
+    if skipped > 0 {
+        info!("Skipped {skipped} repositories with no commits since {since} (unix time)");
+    }
+    repositories
+}
+
+/// Put the repositories in a stable order, ties are broken by path
+pub fn sort_repositories(repositories: &mut [PathBuf], order: RepositoryOrder) {
+    match order {
+        RepositoryOrder::Name => {
+            repositories.sort_by(|a, b| a.file_name().cmp(&b.file_name()).then_with(|| a.cmp(b)));
         }
+        RepositoryOrder::Path => repositories.sort(),
+        RepositoryOrder::Mtime => repositories.sort_by_cached_key(|path| {
+            let modified = fs::metadata(path)
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, path.clone())
+        }),
     }
+}
 
-    Ok(repositories)
+/// Parse a date given as `YYYY-MM-DD` (midnight UTC), as an RFC 3339 date and time
+/// like `2024-01-31T09:30:00+01:00` or as unix time in seconds
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    if let Ok(seconds) = date.parse::<i64>() {
+        return Ok(seconds);
+    }
+    let invalid = || {
+        format!(
+            "Invalid date {date:?}, expected YYYY-MM-DD, an RFC 3339 date and time or unix time"
+        )
+    };
+    let (date, time) = match date.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (date, None),
+    };
+    let seconds = match time {
+        Some(time) => parse_time(time).ok_or_else(invalid)?,
+        None => 0,
+    };
+    let mut parts = date.splitn(3, '-').map(str::parse::<i64>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+    Ok(days_from_civil(year, month, day) * 86_400 + seconds)
+}
+
+/// How many days a month has in the proleptic Gregorian calendar, so dates like 2024-02-31 are rejected
+const fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Seconds after midnight UTC for the time part of an RFC 3339 date, e.g. `09:30:00Z` or `09:30:00.250-05:00`,
+/// fractions of a second are dropped
+fn parse_time(time: &str) -> Option<i64> {
+    let (clock, offset) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let (clock, zone) = time.split_at(time.rfind(['+', '-'])?);
+        let (hours, minutes) = zone[1..].split_once(':')?;
+        let offset = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+        (
+            clock,
+            if zone.starts_with('-') {
+                -offset
+            } else {
+                offset
+            },
+        )
+    };
+    let clock = clock.split_once('.').map_or(clock, |(whole, _)| whole);
+    let mut parts = clock.splitn(3, ':').map(str::parse::<i64>);
+    let (Some(Ok(hours)), Some(Ok(minutes)), Some(Ok(seconds))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) || !(0..=60).contains(&seconds) {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds - offset)
+}
+
+/// The UTC date and time of a unix timestamp in ISO 8601, e.g. `2024-01-31T09:30:00Z`
+pub fn format_date(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86_400));
+    let seconds = timestamp.rem_euclid(86_400);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
 }
 
-/// Take a list of repository paths and validate them, returning the list repositories with the invalid ones removed
-pub fn validate_repositories(mut repositories: Vec<PathBuf>) -> Vec<PathBuf> {
+/// The year, month and day for a number of days since 1970-01-01, the inverse of `days_from_civil`
+const fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months count from March, the same as `days_from_civil`
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Days since 1970-01-01 for a date in the proleptic Gregorian calendar
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Count years from March, so the leap day is at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Take a list of repository paths and validate them, returning the list repositories with the invalid ones removed.
+/// A detached HEAD is only a problem when the history is read from HEAD
+pub fn validate_repositories(
+    mut repositories: Vec<PathBuf>,
+    start_point: &StartPoint,
+) -> Vec<PathBuf> {
     repositories.retain(|path| {
         let path = PathBuf::from(path);
         match Repository::open(path.as_path()) {
             Ok(r) => {
                 if r.head().is_err() {
-                    warn!("Skipping repository with no HEAD {:?}", path);
-                    return false;
-                }
-                if r.is_bare() {
-                    warn!("Skipping bare repository {:?}", path);
+                    record(
+                        Warning::RepositorySkipped,
+                        format!("Skipping repository with no HEAD {}", path.display()),
+                    );
                     return false;
                 }
                 if r.is_empty().unwrap_or(false) {
-                    warn!("Skipping empty repository {:?}", path);
+                    record(
+                        Warning::RepositorySkipped,
+                        format!("Skipping empty repository {}", path.display()),
+                    );
                     return false;
                 }
-                if r.head_detached().unwrap_or(false) {
-                    warn!("Skipping detached head repository {:?}", path);
+                if *start_point == StartPoint::Head && r.head_detached().unwrap_or(false) {
+                    record(
+                        Warning::RepositorySkipped,
+                        format!("Skipping detached head repository {}", path.display()),
+                    );
                     return false;
                 }
                 true
             }
             Err(e) => {
-                error!("Failed to open repository: {}", e);
+                record(
+                    Warning::RepositorySkipped,
+                    format!("Failed to open repository {}: {e}", path.display()),
+                );
                 false
             }
         }
     });
     repositories
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_date, validate_date_range};
+    use crate::test_support::{arguments, lines, read, Fixture};
+    use std::path::Path;
+
+    #[test]
+    fn dates_that_dont_exist_are_invalid() {
+        assert_eq!(parse_date("2024-02-29"), Ok(1_709_164_800));
+        assert_eq!(parse_date("2024-12-31T23:59:59Z"), Ok(1_735_689_599));
+        for date in [
+            "2024-02-30",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-04-31",
+            "2024-11-31T09:30:00Z",
+            "2024-13-01",
+            "2024-01-00",
+        ] {
+            assert!(parse_date(date).is_err(), "{date}");
+        }
+        assert_eq!(parse_date("2000-02-29"), Ok(951_782_400));
+        assert!(arguments(Path::new("."), &["--since", "2024-02-31"]).is_err());
+    }
+
+    #[test]
+    fn commits_exactly_on_the_bounds_are_kept() -> anyhow::Result<()> {
+        let fixture = Fixture::new()?;
+        for (time, file) in [
+            (1_703_980_799, "before.txt"),
+            (1_703_980_800, "since.txt"),
+            (1_704_067_200, "until.txt"),
+            (1_704_067_201, "after.txt"),
+        ] {
+            fixture.commit("Ann", time, &[(file, Some("one"))])?;
+        }
+        for backend in ["libgit2", "git-cli"] {
+            let logs = read(
+                &fixture.path(),
+                &[
+                    "--backend",
+                    backend,
+                    "--since",
+                    "2023-12-31",
+                    "--until",
+                    "2024-01-01",
+                ],
+            )?;
+            assert_eq!(
+                lines(&logs),
+                ["1703980800|Ann|A|since.txt", "1704067200|Ann|A|until.txt"],
+                "{backend}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn since_must_not_be_after_until() {
+        assert!(validate_date_range(Some(200), Some(100)).is_err());
+        assert!(validate_date_range(Some(100), Some(100)).is_ok());
+        assert!(validate_date_range(Some(100), None).is_ok());
+        assert!(validate_date_range(None, Some(100)).is_ok());
+    }
+}
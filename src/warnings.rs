@@ -0,0 +1,76 @@
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The kinds of problem that can happen many times during a run,
+/// these are counted and summarised at the end rather than logged one by one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Warning {
+    RepositorySkipped,
+    RepositoryFailed,
+    CommitUnreadable,
+    DiffFailed,
+    StoreFailed,
+    PathUnreadable,
+}
+
+impl Warning {
+    const fn description(self) -> &'static str {
+        match self {
+            Self::RepositorySkipped => "repositories were skipped",
+            Self::RepositoryFailed => {
+                "repositories failed to read and were left out, some of their commits may still be in the log"
+            }
+            Self::CommitUnreadable => "commits could not be read",
+            Self::DiffFailed => "commits failed to diff",
+            Self::StoreFailed => "commits failed to store their changes",
+            Self::PathUnreadable => "file changes could not be parsed (e.g. non-UTF-8 paths)",
+        }
+    }
+}
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static COUNTS: Mutex<BTreeMap<Warning, u64>> = Mutex::new(BTreeMap::new());
+
+/// Log every warning as it happens, as well as the summary
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+/// Turn off the summary and any other warnings about the run, for --no-warnings
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Count a warning, the detail is only logged in verbose mode
+pub fn record(warning: Warning, detail: impl Display) {
+    if VERBOSE.load(Ordering::Relaxed) {
+        warn!("{detail}");
+    }
+    if let Ok(mut counts) = COUNTS.lock() {
+        *counts.entry(warning).or_default() += 1;
+    }
+}
+
+/// Log a line for each kind of warning that happened during the run
+pub fn log_summary() {
+    if !enabled() {
+        return;
+    }
+    let Ok(counts) = COUNTS.lock() else {
+        return;
+    };
+    for (warning, count) in counts.iter() {
+        warn!("{count} {}", warning.description());
+    }
+    if !counts.is_empty() && !VERBOSE.load(Ordering::Relaxed) {
+        warn!("Run with --verbose to see the details");
+    }
+}